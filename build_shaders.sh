@@ -5,7 +5,9 @@ build() {
 }
 
 build geometry.vert
+build geometry_instanced.vert
 build geometry.frag
 build background.vert
 build background.frag
-
+build grid.vert
+build grid.frag
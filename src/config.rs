@@ -2,7 +2,6 @@ use serde::{Serialize, Deserialize};
 use std::{env, fs};
 use std::path::{PathBuf};
 use log::{warn, info};
-use std::error::Error;
 use crate::Result;
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -15,6 +14,9 @@ pub struct Graphics {
     pub use_low_power_gpu: bool,
     pub sample_count: u32,
     pub tolerance: f32,
+    /// Draw the play field on the GPU from a texture of its cells
+    #[serde(default)]
+    pub gpu_grid: bool,
 }
 
 pub fn get_config_file() -> Result<PathBuf> {
@@ -22,12 +24,18 @@ pub fn get_config_file() -> Result<PathBuf> {
     Ok(exe_path.with_extension("toml"))
 }
 
+/// Directory next to the executable where user made puzzles are loaded from
+pub fn get_puzzle_dir() -> Result<PathBuf> {
+    let exe_path = env::current_exe()?;
+    Ok(exe_path.with_file_name("puzzles"))
+}
+
 fn load_config_file() -> Result<Config> {
     let config_file = get_config_file()?;
     // if config_file.exists() {
     let conf_str = fs::read_to_string(&config_file)?;
     let config = toml::from_str(conf_str.as_str())?;
-    return Ok(config);
+    Ok(config)
 }
 
 fn save_config_file(config: &Config) -> Result<()> {
@@ -51,9 +59,12 @@ pub fn load_config() -> Config {
                     sample_count: 4,
                     tolerance: 0.02,
                     use_low_power_gpu: true,
+                    gpu_grid: false,
                 }
             };
-            save_config_file(&config);
+            if let Err(err) = save_config_file(&config) {
+                warn!("Failed to save the default configuration: {}", err);
+            }
             config
         }
     }
@@ -3,8 +3,11 @@ use crate::engine::gpu;
 use lyon::tessellation as Tes;
 use lyon::path::math as LyM;
 use lyon::tessellation::{FillTessellator, StrokeTessellator, VertexBuffers, FillRule, FillOptions, BuffersBuilder, TessellationError};
-use lyon::path::{Path};
+use lyon::path::{Path, PathEvent};
+use lyon::path::iterator::PathIterator;
 use lyon::path::builder::{SvgPathBuilder};
+use lyon::algorithms::walk::{walk_along_path, RepeatedPattern};
+use lyon::math::{Point, Vector};
 
 pub enum BluePrint {
     SvgPath(Box<dyn Fn(&mut dyn SvgPathBuilder)>),
@@ -22,6 +25,124 @@ pub struct EntityBuilder {
 
 const TOLERANCE: f32 = 0.02;
 
+/// How the outline of a geo entity is tessellated. The stroke width is set per instance.
+#[derive(Clone, Debug)]
+pub struct StrokeStyle {
+    pub line_join: Tes::LineJoin,
+    pub line_cap: Tes::LineCap,
+    /// How far a miter join reaches out, in stroke widths, before it is cut off
+    pub miter_limit: f32,
+    /// Lengths of the dashes and of the gaps between them in turn, a solid stroke if `None`
+    pub dashes: Option<Vec<f32>>,
+}
+
+impl Default for StrokeStyle {
+    fn default() -> Self {
+        StrokeStyle {
+            line_join: Tes::StrokeOptions::DEFAULT_LINE_JOIN,
+            line_cap: Tes::StrokeOptions::DEFAULT_LINE_CAP,
+            miter_limit: Tes::StrokeOptions::DEFAULT_MITER_LIMIT,
+            dashes: None,
+        }
+    }
+}
+
+impl StrokeStyle {
+    /// Lyon asserts on a miter limit below 1, and dashes of no length would never end
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.miter_limit.is_nan() || self.miter_limit < Tes::StrokeOptions::MINIMUM_MITER_LIMIT {
+            return Err("the miter limit is below 1");
+        }
+        if let Some(dashes) = &self.dashes {
+            if dashes.is_empty() || !dashes.iter().all(|&length| length > 0.0) {
+                return Err("dash lengths must be positive");
+            }
+        }
+        Ok(())
+    }
+
+    fn options(&self) -> Tes::StrokeOptions {
+        Tes::StrokeOptions::tolerance(TOLERANCE)
+            .with_line_join(self.line_join)
+            .with_line_cap(self.line_cap)
+            .with_miter_limit(self.miter_limit)
+    }
+}
+
+/// Cuts `path` into the dashes of `dashes`, starting each sub-path with a dash. Dashes
+/// follow the path around corners rather than cutting across them.
+fn dash_path(path: &Path, dashes: &[f32]) -> Path {
+    let mut builder = Path::builder();
+    let mut points: Vec<Point> = Vec::new();
+    let mut subpaths = Vec::new();
+    for event in path.iter().flattened(TOLERANCE) {
+        match event {
+            PathEvent::Begin { at } => points = vec![at],
+            PathEvent::Line { to, .. } => points.push(to),
+            PathEvent::End { first, close, .. } => {
+                if close {
+                    points.push(first);
+                }
+                subpaths.push(std::mem::take(&mut points));
+            }
+            // Flattening leaves only lines
+            _ => {}
+        }
+    }
+
+    for points in subpaths {
+        // The distance along the sub-path of each of its points
+        let mut length = 0.0;
+        let corners: Vec<(f32, Point)> = points.iter().enumerate()
+            .map(|(i, &point)| {
+                if i > 0 {
+                    length += (point - points[i - 1]).length();
+                }
+                (length, point)
+            })
+            .collect();
+
+        let mut events = vec![PathEvent::Begin { at: points[0] }];
+        events.extend(points.windows(2).map(|line| PathEvent::Line { from: line[0], to: line[1] }));
+        events.push(PathEvent::End { last: points[points.len() - 1], first: points[0], close: false });
+
+        let mut in_dash = false;
+        let mut next_corner = 0;
+        {
+            let mut pattern = RepeatedPattern {
+                callback: |position: Point, _tangent: Vector, distance: f32| {
+                    if in_dash {
+                        while next_corner < corners.len() && corners[next_corner].0 < distance {
+                            builder.line_to(corners[next_corner].1);
+                            next_corner += 1;
+                        }
+                        builder.line_to(position);
+                        builder.end(false);
+                    } else {
+                        while next_corner < corners.len() && corners[next_corner].0 <= distance {
+                            next_corner += 1;
+                        }
+                        builder.begin(position);
+                    }
+                    in_dash = !in_dash;
+                    true
+                },
+                intervals: dashes,
+                index: 0,
+            };
+            walk_along_path(events.into_iter(), 0.0, &mut pattern);
+        }
+        // The last dash runs to the end of the sub-path
+        if in_dash {
+            for &(_, corner) in &corners[next_corner..] {
+                builder.line_to(corner);
+            }
+            builder.end(false);
+        }
+    }
+    builder.build()
+}
+
 
 impl EntityBuilder {
 
@@ -65,17 +186,24 @@ impl EntityBuilder {
         Ok(())
     }
 
-    pub fn build_geo(&mut self, path: &Path, instances: usize, scale: f32, fill: bool, stroke: bool) -> Result<GeoEntity, TessellationError> {
+    /// An entity of `path`, with an outline if given a `stroke` style, which should have
+    /// passed `StrokeStyle::validate`
+    pub fn build_geo(&mut self, path: &Path, instances: usize, scale: f32, fill: bool, stroke: Option<&StrokeStyle>) -> Result<GeoEntity, TessellationError> {
         let mut geometry = VertexBuffers::new();
-        let entity_id = 0; // TODO: This needs to be changed for multiple entities in the same buffer
+        // Every entity has a primitive buffer of its own, starting at its first instance
+        let entity_id = 0;
         let fill_id = 0;
         if fill {
             self.tes_fill(path, &mut geometry, entity_id)?;
         }
         let range_fill = fill_id..geometry.indices.len() as u32;
         let stroke_id = range_fill.end;
-        if stroke {
-            self.tes_stroke(path, &mut geometry, entity_id)?;
+        if let Some(style) = stroke {
+            self.stroke_opts = style.options();
+            match &style.dashes {
+                Some(dashes) => self.tes_stroke(&dash_path(path, dashes), &mut geometry, entity_id)?,
+                None => self.tes_stroke(path, &mut geometry, entity_id)?,
+            }
         }
         let range_stroke = stroke_id..geometry.indices.len() as u32;
         Ok(GeoEntity::new(geometry, range_fill, range_stroke, scale, instances))
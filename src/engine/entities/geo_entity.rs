@@ -1,5 +1,7 @@
-use crate::engine::{gpu, RenderData};
-use crate::engine::entities::{EntityRenderer, init_device_buffers};
+use crate::engine::{gpu, RenderData, EngineResult, Layer};
+use crate::engine::entities::{EntityRenderer, Instance, Instances, init_device_buffers};
+use crate::engine::entities::instance::DirtyRanges;
+use crate::engine::error::EngineError;
 use lyon::tessellation::{FillTessellator, StrokeTessellator, VertexBuffers, FillRule, FillOptions, BuffersBuilder, TessellationError};
 use std::ops::Range;
 
@@ -8,8 +10,45 @@ pub struct GeoEntity {
     pub(crate) instances: usize,
     geometry: VertexBuffers<gpu::GpuVertex, u16>,
     pub(crate) ranges: [ Range<u32>; 2 ],
-    pub primitives: Vec<gpu::Primitive>,
+    pub(crate) layer: Layer,
+    /// Entities of a layer are drawn from the lowest order up
+    pub(crate) order: i32,
+    primitives: Vec<gpu::Primitive>,
+    /// Instances given back with `free_instance`, handed out again first
+    free: Vec<usize>,
+    dirty: DirtyRanges,
     pub(crate) renderer: Option<EntityRenderer>,
+    pub(crate) prims: Option<PrimBuffer>,
+}
+
+/// The primitives of an entity on the GPU, bound on their own so that every entity
+/// reads its own
+pub(crate) struct PrimBuffer {
+    pub(crate) buffer: wgpu::Buffer,
+    /// Number of primitives the buffer has room for
+    capacity: usize,
+    pub(crate) bind_group: wgpu::BindGroup,
+}
+
+impl PrimBuffer {
+    fn new(device: &wgpu::Device, rd: &RenderData, len: usize) -> Self {
+        let capacity = gpu::prim_buffer_capacity(len);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Prims ssbo"),
+            size: (capacity * std::mem::size_of::<gpu::Primitive>()) as u64,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Prims bind group"),
+            layout: &rd.prims_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(buffer.as_entire_buffer_binding()),
+            }],
+        });
+        PrimBuffer { buffer, capacity, bind_group }
+    }
 }
 
 impl GeoEntity {
@@ -47,105 +86,261 @@ impl GeoEntity {
     ) -> Self {
 
 
-        let prim_count = instances;
-        // We cannot send more than PRIM_BUFFER_LEN per bound buffer
-        // TODO: Check this earlier?
-        assert!(prim_count <= gpu::PRIM_BUFFER_LEN);
-        let mut primitives = Vec::with_capacity(gpu::PRIM_BUFFER_LEN);
-        for _ in 0..gpu::PRIM_BUFFER_LEN {
-            primitives.push(gpu::Primitive::new_with_scale(scale) );
-        }
+        let primitives = vec![gpu::Primitive::new_with_scale(scale); instances];
 
 
         GeoEntity {
             scale,
             geometry,
             ranges: [ range_fill, range_stroke ],
+            layer: Layer::Pieces,
+            order: 0,
             instances,
             primitives,
+            free: Vec::new(),
+            dirty: DirtyRanges::default(),
             renderer: None,
+            prims: None,
         }
     }
 
-    pub(crate) fn init_render(&mut self, device: &wgpu::Device, rd: &RenderData) {
+    /// Changes the number of instances, added ones starting out like those of a new entity.
+    /// The buffer on the GPU grows along with it on the next upload.
+    pub fn set_instances(&mut self, instances: usize) {
+        self.primitives.resize(instances, gpu::Primitive::new_with_scale(self.scale));
+        self.free.retain(|&index| index < instances);
+        if instances > self.instances {
+            self.dirty.mark(self.instances..instances);
+        }
+        self.instances = instances;
+    }
 
-        let (vertex_buffer, index_buffer) = init_device_buffers(&self.geometry, device);
+    pub fn set_layer(&mut self, layer: Layer, order: i32) {
+        self.layer = layer;
+        self.order = order;
+    }
+
+    pub fn instance_count(&self) -> usize {
+        self.instances
+    }
+
+    /// Whether the entity is drawn with its primitives as an instance vertex buffer rather
+    /// than read from a storage buffer
+    pub(crate) fn is_instanced(&self) -> bool {
+        self.instances >= gpu::INSTANCED_MIN_LEN
+    }
+
+    pub fn instance(&mut self, index: usize) -> EngineResult<Instance<'_>> {
+        if index >= self.instances {
+            return Err(EngineError::InstanceOutOfRange { index, instances: self.instances });
+        }
+        Ok(Instance::new(&mut self.primitives[index], index, &mut self.dirty))
+    }
+
+    pub fn instance_range(&mut self, range: Range<usize>) -> EngineResult<Instances<'_>> {
+        if range.start > range.end || range.end > self.instances {
+            return Err(EngineError::InstanceOutOfRange {
+                index: range.end.saturating_sub(1).max(range.start),
+                instances: self.instances,
+            });
+        }
+        let start = range.start;
+        Ok(Instances::new(&mut self.primitives[range], start, &mut self.dirty))
+    }
+
+    /// A new instance, reusing a freed one if there is any, looking like those of a new entity
+    pub fn alloc_instance(&mut self) -> usize {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                self.set_instances(self.instances + 1);
+                self.instances - 1
+            }
+        };
+        self.primitives[index] = gpu::Primitive::new_with_scale(self.scale);
+        self.dirty.mark(index..index + 1);
+        index
+    }
 
+    /// Hides the instance and keeps it for the next `alloc_instance`
+    pub fn free_instance(&mut self, index: usize) -> EngineResult<()> {
+        self.instance(index)?.hide();
+        if !self.free.contains(&index) {
+            self.free.push(index);
+        }
+        Ok(())
+    }
 
-        let vert = wgpu::include_spirv!(concat!(env!("OUT_DIR"), "/spirv/geometry.vert.spv"));
-        let vert_shader = device.create_shader_module(&vert);
-        let frag = wgpu::include_spirv!(concat!(env!("OUT_DIR"), "/spirv/geometry.frag.spv"));
-        let frag_shader = device.create_shader_module(&frag);
-
-        let mut render_pipeline_descriptor = wgpu::RenderPipelineDescriptor {
-            label: None,
-            layout: Some(&rd.pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &vert_shader,
-                entry_point: "main",
-                buffers: &[wgpu::VertexBufferLayout {
-                    array_stride: std::mem::size_of::<gpu::GpuVertex>() as u64,
-                    step_mode: wgpu::InputStepMode::Vertex,
-                    attributes: &[
-                        wgpu::VertexAttribute {
-                            offset: 0,
-                            format: wgpu::VertexFormat::Float32x2,
-                            shader_location: 0,
-                        },
-                        wgpu::VertexAttribute {
-                            offset: 8,
-                            format: wgpu::VertexFormat::Float32x2,
-                            shader_location: 1,
-                        },
-                        wgpu::VertexAttribute {
-                            offset: 16,
-                            format: wgpu::VertexFormat::Sint32,
-                            shader_location: 2,
-                        },
-                    ],
-                }],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &frag_shader,
-                entry_point: "main",
-                targets: &[
-                    wgpu::ColorTargetState {
-                        format: wgpu::TextureFormat::Bgra8Unorm,
-                        blend: None,
-                        write_mask: wgpu::ColorWrite::ALL,
-                    },
-                ],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                front_face: wgpu::FrontFace::Ccw,
-                strip_index_format: None,
-                cull_mode: None,
-                clamp_depth: false,
-                conservative: false,
-            },
-            depth_stencil: rd.depth_stencil_state.clone(),
-            multisample: wgpu::MultisampleState {
-                count: rd.sample_count,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
+    /// Writes the instances changed since the last upload to the GPU, all of them if they
+    /// have outgrown the buffer
+    pub(crate) fn upload_primitives(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, rd: &RenderData) {
+        let len = self.primitives.len();
+        if self.prims.as_ref().is_none_or(|prims| prims.capacity < len) {
+            self.prims = Some(PrimBuffer::new(device, rd, len));
+            self.dirty.mark(0..len);
+        }
+        let prims = match &self.prims {
+            Some(prims) => prims,
+            None => return,
         };
+        let prim_size = std::mem::size_of::<gpu::Primitive>();
+        for range in self.dirty.take() {
+            // Instances dropped by `set_instances` since they were marked
+            let range = range.start..range.end.min(len);
+            if range.start < range.end {
+                queue.write_buffer(
+                    &prims.buffer,
+                    (range.start * prim_size) as u64,
+                    bytemuck::cast_slice(&self.primitives[range]),
+                );
+            }
+        }
+    }
 
-        let render_pipeline = device.create_render_pipeline(&render_pipeline_descriptor);
+    /// Creates the buffers of the entity on the GPU, at any time once the engine has
+    /// initialized its render data
+    pub(crate) fn init_render(&mut self, device: &wgpu::Device, rd: &RenderData) {
+        self.prims = Some(PrimBuffer::new(device, rd, self.primitives.len()));
+        self.dirty.mark(0..self.primitives.len());
 
-        // TODO: this isn't what we want: we'd need the equivalent of VK_POLYGON_MODE_LINE,
-        // but it doesn't seem to be exposed by wgpu?
-        render_pipeline_descriptor.primitive.topology = wgpu::PrimitiveTopology::LineList;
+        let (vertex_buffer, index_buffer) = init_device_buffers(&self.geometry, device);
 
         self.renderer = Some(EntityRenderer{
             vertex_buffer,
             index_buffer,
-            // vert_shader,
-            // frag_shader,
-            render_pipeline,
         })
     }
+}
+
+/// The pipeline geo entities are drawn with. The instanced one takes the primitives as a
+/// second vertex buffer, stepped per instance, and only binds the globals.
+pub(crate) fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    sample_count: u32,
+    instanced: bool,
+) -> wgpu::RenderPipeline {
+    let vert = if instanced {
+        wgpu::include_spirv!(concat!(env!("OUT_DIR"), "/spirv/geometry_instanced.vert.spv"))
+    } else {
+        wgpu::include_spirv!(concat!(env!("OUT_DIR"), "/spirv/geometry.vert.spv"))
+    };
+    let vert_shader = device.create_shader_module(&vert);
+    let frag = wgpu::include_spirv!(concat!(env!("OUT_DIR"), "/spirv/geometry.frag.spv"));
+    let frag_shader = device.create_shader_module(&frag);
+
+    let buffers = [
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<gpu::GpuVertex>() as u64,
+            step_mode: wgpu::InputStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    format: wgpu::VertexFormat::Float32x2,
+                    shader_location: 0,
+                },
+                wgpu::VertexAttribute {
+                    offset: 8,
+                    format: wgpu::VertexFormat::Float32x2,
+                    shader_location: 1,
+                },
+                wgpu::VertexAttribute {
+                    offset: 16,
+                    format: wgpu::VertexFormat::Sint32,
+                    shader_location: 2,
+                },
+            ],
+        },
+        // Follows the layout of `gpu::Primitive`, leaving out the padding
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<gpu::Primitive>() as u64,
+            step_mode: wgpu::InputStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    format: wgpu::VertexFormat::Float32x4,
+                    shader_location: 3,
+                },
+                wgpu::VertexAttribute {
+                    offset: 16,
+                    format: wgpu::VertexFormat::Float32x4,
+                    shader_location: 4,
+                },
+                wgpu::VertexAttribute {
+                    offset: 32,
+                    format: wgpu::VertexFormat::Float32x2,
+                    shader_location: 5,
+                },
+                wgpu::VertexAttribute {
+                    offset: 40,
+                    format: wgpu::VertexFormat::Float32,
+                    shader_location: 6,
+                },
+                wgpu::VertexAttribute {
+                    offset: 44,
+                    format: wgpu::VertexFormat::Float32,
+                    shader_location: 7,
+                },
+                wgpu::VertexAttribute {
+                    offset: 48,
+                    format: wgpu::VertexFormat::Float32,
+                    shader_location: 8,
+                },
+                wgpu::VertexAttribute {
+                    offset: 52,
+                    format: wgpu::VertexFormat::Sint32,
+                    shader_location: 9,
+                },
+                wgpu::VertexAttribute {
+                    offset: 56,
+                    format: wgpu::VertexFormat::Float32x2,
+                    shader_location: 10,
+                },
+            ],
+        },
+    ];
 
-}
\ No newline at end of file
+    let mut render_pipeline_descriptor = wgpu::RenderPipelineDescriptor {
+        label: None,
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &vert_shader,
+            entry_point: "main",
+            buffers: if instanced { &buffers[..] } else { &buffers[..1] },
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &frag_shader,
+            entry_point: "main",
+            targets: &[
+                wgpu::ColorTargetState {
+                    format: wgpu::TextureFormat::Bgra8Unorm,
+                    blend: Some(gpu::PREMULTIPLIED_BLEND),
+                    write_mask: wgpu::ColorWrite::ALL,
+                },
+            ],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            front_face: wgpu::FrontFace::Ccw,
+            strip_index_format: None,
+            cull_mode: None,
+            clamp_depth: false,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+    };
+
+    let render_pipeline = device.create_render_pipeline(&render_pipeline_descriptor);
+
+    // TODO: this isn't what we want: we'd need the equivalent of VK_POLYGON_MODE_LINE,
+    // but it doesn't seem to be exposed by wgpu?
+    render_pipeline_descriptor.primitive.topology = wgpu::PrimitiveTopology::LineList;
+
+    render_pipeline
+}
@@ -0,0 +1,260 @@
+use crate::engine::gpu;
+use std::ops::Range;
+
+/// Past this many separate ranges, uploading the span of all of them is cheaper than
+/// a write per range
+const MAX_DIRTY_RANGES: usize = 16;
+
+/// Instances of an entity changed since its last upload, as sorted ranges that don't touch
+#[derive(Default)]
+pub(crate) struct DirtyRanges {
+    ranges: Vec<Range<usize>>,
+}
+
+impl DirtyRanges {
+    pub(crate) fn mark(&mut self, range: Range<usize>) {
+        if range.start >= range.end {
+            return;
+        }
+        // Ranges overlapping or touching the new one are merged into it
+        let first = self.ranges.partition_point(|r| r.end < range.start);
+        let last = self.ranges.partition_point(|r| r.start <= range.end);
+        let merged = if first < last {
+            self.ranges[first].start.min(range.start)..self.ranges[last - 1].end.max(range.end)
+        } else {
+            range
+        };
+        self.ranges.splice(first..last, std::iter::once(merged));
+
+        if self.ranges.len() > MAX_DIRTY_RANGES {
+            let span = self.ranges[0].start..self.ranges[self.ranges.len() - 1].end;
+            self.ranges = vec![span];
+        }
+    }
+
+    pub(crate) fn take(&mut self) -> Vec<Range<usize>> {
+        std::mem::take(&mut self.ranges)
+    }
+}
+
+/// Applies `f` to `prim`, marking it dirty only if that changed anything
+fn update(prim: &mut gpu::Primitive, index: usize, dirty: &mut DirtyRanges, f: impl FnOnce(&mut gpu::Primitive)) {
+    let before = *prim;
+    f(prim);
+    if bytemuck::bytes_of(&before) != bytemuck::bytes_of(prim) {
+        dirty.mark(index..index + 1);
+    }
+}
+
+/// One instance of a geo entity, changes to it go to the GPU with the next frame
+pub struct Instance<'a> {
+    prim: &'a mut gpu::Primitive,
+    index: usize,
+    dirty: &'a mut DirtyRanges,
+}
+
+impl<'a> Instance<'a> {
+    pub(crate) fn new(prim: &'a mut gpu::Primitive, index: usize, dirty: &'a mut DirtyRanges) -> Self {
+        Instance { prim, index, dirty }
+    }
+
+    fn update(&mut self, f: impl FnOnce(&mut gpu::Primitive)) -> &mut Self {
+        update(self.prim, self.index, self.dirty, f);
+        self
+    }
+
+    pub fn set_translate(&mut self, translate: [f32; 2]) -> &mut Self {
+        self.update(|prim| prim.translate = translate)
+    }
+
+    pub fn set_color(&mut self, color: [f32; 4]) -> &mut Self {
+        self.update(|prim| prim.color = color)
+    }
+
+    pub fn set_stroke_color(&mut self, color: [f32; 4]) -> &mut Self {
+        self.update(|prim| prim.color_stroke = color)
+    }
+
+    /// How far the stroke reaches out on each side of the outline, before the scene's
+    /// stroke width scales it
+    pub fn set_width(&mut self, width: f32) -> &mut Self {
+        self.update(|prim| prim.width = width)
+    }
+
+    pub fn set_scale(&mut self, scale: f32) -> &mut Self {
+        self.update(|prim| prim.scale = scale)
+    }
+
+    pub fn set_angle(&mut self, angle: f32) -> &mut Self {
+        self.update(|prim| prim.angle = angle)
+    }
+
+    pub fn set_shading(&mut self, shading: gpu::Shading) -> &mut Self {
+        self.update(|prim| prim.shading = shading as i32)
+    }
+
+    /// Puts the instance in the wave over the empty play field cells, see `Primitive::wave`
+    pub fn set_wave(&mut self, wave: [f32; 2]) -> &mut Self {
+        self.update(|prim| prim.wave = wave)
+    }
+
+    /// Hides both the fill and the stroke, until they are given a color again
+    pub fn hide(&mut self) -> &mut Self {
+        self.update(|prim| {
+            prim.color[3] = 0.0;
+            prim.color_stroke[3] = 0.0;
+        })
+    }
+
+    pub fn color(&self) -> [f32; 4] {
+        self.prim.color
+    }
+}
+
+/// A range of instances of a geo entity, to set many at once
+pub struct Instances<'a> {
+    prims: &'a mut [gpu::Primitive],
+    /// Index of the first instance of the range in the entity
+    start: usize,
+    dirty: &'a mut DirtyRanges,
+}
+
+impl<'a> Instances<'a> {
+    pub(crate) fn new(prims: &'a mut [gpu::Primitive], start: usize, dirty: &'a mut DirtyRanges) -> Self {
+        Instances { prims, start, dirty }
+    }
+
+    pub fn len(&self) -> usize {
+        self.prims.len()
+    }
+
+    /// The instance at `index` within the range
+    pub fn get(&mut self, index: usize) -> Option<Instance<'_>> {
+        let prim = self.prims.get_mut(index)?;
+        Some(Instance::new(prim, self.start + index, self.dirty))
+    }
+
+    /// Calls `f` with every instance and its index within the range
+    pub fn for_each(&mut self, mut f: impl FnMut(usize, &mut Instance<'_>)) -> &mut Self {
+        for (i, prim) in self.prims.iter_mut().enumerate() {
+            f(i, &mut Instance::new(prim, self.start + i, self.dirty));
+        }
+        self
+    }
+
+    fn each(&mut self, mut f: impl FnMut(&mut gpu::Primitive)) -> &mut Self {
+        for (i, prim) in self.prims.iter_mut().enumerate() {
+            update(prim, self.start + i, self.dirty, &mut f);
+        }
+        self
+    }
+
+    pub fn set_color(&mut self, color: [f32; 4]) -> &mut Self {
+        self.each(|prim| prim.color = color)
+    }
+
+    pub fn set_stroke_color(&mut self, color: [f32; 4]) -> &mut Self {
+        self.each(|prim| prim.color_stroke = color)
+    }
+
+    pub fn set_width(&mut self, width: f32) -> &mut Self {
+        self.each(|prim| prim.width = width)
+    }
+
+    pub fn set_scale(&mut self, scale: f32) -> &mut Self {
+        self.each(|prim| prim.scale = scale)
+    }
+
+    pub fn set_shading(&mut self, shading: gpu::Shading) -> &mut Self {
+        self.each(|prim| prim.shading = shading as i32)
+    }
+
+    pub fn hide(&mut self) -> &mut Self {
+        self.each(|prim| {
+            prim.color[3] = 0.0;
+            prim.color_stroke[3] = 0.0;
+        })
+    }
+
+    /// Gives every instance its own color, in order, for as many colors as there are
+    pub fn set_colors(&mut self, colors: impl IntoIterator<Item = [f32; 4]>) -> &mut Self {
+        let mut colors = colors.into_iter();
+        for (i, prim) in self.prims.iter_mut().enumerate() {
+            match colors.next() {
+                Some(color) => update(prim, self.start + i, self.dirty, |prim| prim.color = color),
+                None => break,
+            }
+        }
+        self
+    }
+
+    /// Gives every instance its own position, in order, for as many as there are
+    pub fn set_translates(&mut self, translates: impl IntoIterator<Item = [f32; 2]>) -> &mut Self {
+        let mut translates = translates.into_iter();
+        for (i, prim) in self.prims.iter_mut().enumerate() {
+            match translates.next() {
+                Some(translate) => update(prim, self.start + i, self.dirty, |prim| prim.translate = translate),
+                None => break,
+            }
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn marked(ranges: &[Range<usize>]) -> Vec<Range<usize>> {
+        let mut dirty = DirtyRanges::default();
+        for range in ranges {
+            dirty.mark(range.clone());
+        }
+        dirty.take()
+    }
+
+    #[test]
+    fn merges_overlapping_and_touching_ranges() {
+        assert_eq!(marked(&[0..2, 5..7]), vec![0..2, 5..7]);
+        assert_eq!(marked(&[5..7, 0..2]), vec![0..2, 5..7]);
+        assert_eq!(marked(&[0..2, 2..4]), vec![0..4]);
+        assert_eq!(marked(&[0..3, 1..2]), vec![0..3]);
+        assert_eq!(marked(&[0..2, 5..7, 1..6]), vec![0..7]);
+        assert_eq!(marked(&[4..4, 3..2]), vec![]);
+    }
+
+    #[test]
+    fn spans_everything_past_the_range_limit() {
+        let ranges: Vec<Range<usize>> = (0..=MAX_DIRTY_RANGES).map(|i| i * 2..i * 2 + 1).collect();
+        assert_eq!(marked(&ranges), vec![0..MAX_DIRTY_RANGES * 2 + 1]);
+    }
+
+    #[test]
+    fn take_empties_the_ranges() {
+        let mut dirty = DirtyRanges::default();
+        dirty.mark(1..3);
+        assert_eq!(dirty.take(), vec![1..3]);
+        assert_eq!(dirty.take(), vec![]);
+    }
+
+    #[test]
+    fn only_changes_are_marked() {
+        let mut prims = vec![gpu::Primitive::new_with_scale(1.0); 8];
+        let mut dirty = DirtyRanges::default();
+        let frame = |prims: &mut [gpu::Primitive], dirty: &mut DirtyRanges| {
+            let mut instances = Instances::new(prims, 0, dirty);
+            instances.set_stroke_color([0.0, 0.0, 0.0, 1.0]).set_width(0.3);
+            instances.get(3).unwrap().set_color([1.0, 0.0, 0.0, 1.0]).set_wave([0.5, 0.5]);
+        };
+
+        frame(&mut prims, &mut dirty);
+        assert_eq!(dirty.take(), vec![0..8]);
+
+        // Setting the same values again leaves nothing to upload
+        frame(&mut prims, &mut dirty);
+        assert_eq!(dirty.take(), vec![]);
+
+        Instance::new(&mut prims[5], 5, &mut dirty).set_angle(1.0);
+        assert_eq!(dirty.take(), vec![5..6]);
+    }
+}
@@ -1,11 +1,15 @@
 pub(crate) mod entity_builder;
 pub(crate) mod geo_entity;
 pub(crate) mod bg_entity;
+pub(crate) mod instance;
 
 pub type BgEntity = crate::engine::entities::bg_entity::BgEntity;
 pub type GeoEntity = crate::engine::entities::geo_entity::GeoEntity;
 pub type BluePrint = crate::engine::entities::entity_builder::BluePrint;
 pub type EntityBuilder = crate::engine::entities::entity_builder::EntityBuilder;
+pub type StrokeStyle = crate::engine::entities::entity_builder::StrokeStyle;
+pub type Instance<'a> = crate::engine::entities::instance::Instance<'a>;
+pub type Instances<'a> = crate::engine::entities::instance::Instances<'a>;
 
 use lyon::tessellation as Tes;
 use lyon::path::math as LyM;
@@ -14,7 +18,7 @@ use lyon::path::{Path};
 use lyon::path::builder::{SvgPathBuilder};
 use std::ops::Range;
 use crate::engine::{gpu, RenderData};
-use crate::engine::gpu::{Primitive, PRIM_BUFFER_LEN};
+use crate::engine::gpu::Primitive;
 use wgpu::{Device};
 use wgpu::util::DeviceExt;
 use lyon::math::Point;
@@ -25,8 +29,9 @@ pub enum Entity {
     Geo(GeoEntity),
 }
 
+#[derive(Clone, Debug)]
 pub struct EntityToken<'a> {
-    index: usize,
+    pub(crate) index: usize,
     stroke_range: Range<u32>,
     fill_range: Range<u32>,
     pub label: Option<&'a str>,
@@ -34,8 +39,8 @@ pub struct EntityToken<'a> {
 
 impl<'a> EntityToken<'a> {
     pub(crate) fn new(index: usize, entity: &GeoEntity) -> Self {
-        let stroke_range = entity.ranges[0].clone();
-        let fill_range = entity.ranges[1].clone();
+        let fill_range = entity.ranges[0].clone();
+        let stroke_range = entity.ranges[1].clone();
         EntityToken{
             index,
             stroke_range,
@@ -48,9 +53,6 @@ impl<'a> EntityToken<'a> {
 pub struct EntityRenderer {
     pub(crate) vertex_buffer: wgpu::Buffer,
     pub(crate) index_buffer: wgpu::Buffer,
-    // vert_shader: wgpu::ShaderModule,
-    // frag_shader: wgpu::ShaderModule,
-    pub(crate) render_pipeline: wgpu::RenderPipeline,
 }
 
 fn init_device_buffers<V>(vbuffer: &VertexBuffers<V, u16>, device: &Device)
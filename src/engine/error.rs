@@ -6,7 +6,11 @@ use crate::engine::error::EngineError::TesselationError;
 #[derive(Debug)]
 pub enum EngineError {
     UnknownError,
-    TesselationError(lyon::tessellation::TessellationError)
+    TesselationError(lyon::tessellation::TessellationError),
+    /// The entity of a token has been removed
+    NoSuchEntity(usize),
+    InstanceOutOfRange { index: usize, instances: usize },
+    InvalidStrokeStyle(&'static str),
 }
 
 impl From<lyon::tessellation::TessellationError> for EngineError {
@@ -19,7 +23,11 @@ impl fmt::Display for EngineError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             EngineError::UnknownError => write!(f, "unknown error"),
-            EngineError::TesselationError(te) => write!(f, "tesselation error: {:?}", te)
+            EngineError::TesselationError(te) => write!(f, "tesselation error: {:?}", te),
+            EngineError::NoSuchEntity(index) => write!(f, "entity {} has been removed", index),
+            EngineError::InstanceOutOfRange { index, instances } =>
+                write!(f, "instance {} is out of range, the entity has {}", index, instances),
+            EngineError::InvalidStrokeStyle(reason) => write!(f, "invalid stroke style: {}", reason),
         }
 
     }
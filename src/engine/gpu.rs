@@ -6,6 +6,10 @@ pub struct Globals {
     pub(crate) resolution: [f32; 2],
     pub(crate) scroll_offset: [f32; 2],
     pub(crate) zoom: f32,
+    /// Scales the stroke width of every instance
+    pub(crate) stroke_width: f32,
+    /// Seconds since the engine started, for animating in the shaders
+    pub(crate) time: f32,
     pub(crate) _pad: f32,
 }
 
@@ -29,13 +33,24 @@ pub struct Primitive {
     pub color_stroke: [f32; 4],
 
     pub translate: [f32; 2],
-    pub z_index: i32,
     pub width: f32,
-
     pub angle: f32,
+
     pub scale: f32,
-    _pad1: i32,
-    _pad2: i32,
+    /// A `Shading`, for the fill only
+    pub shading: i32,
+    /// Where the instance lies in the wave of light running over the empty play field cells,
+    /// as fractions of the field's width and height. Negative to leave the stroke color be.
+    pub wave: [f32; 2],
+}
+
+/// How the fill of an instance is colored
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Shading {
+    /// The fill color as is
+    Flat = 0,
+    /// Darkens the fill towards the bottom right of the shape
+    Gradient = 1,
 }
 
 impl Primitive {
@@ -53,12 +68,11 @@ impl Primitive {
         color: [0.0; 4],
         color_stroke: [0.0; 4],
         translate: [0.0; 2],
-        z_index: 0,
         width: 0.0,
         angle: 0.0,
         scale: 1.0,
-        _pad1: 0,
-        _pad2: 0,
+        shading: Shading::Flat as i32,
+        wave: [-1.0, -1.0],
     };
 }
 
@@ -73,7 +87,32 @@ pub struct BgPoint {
 unsafe impl bytemuck::Pod for BgPoint {}
 unsafe impl bytemuck::Zeroable for BgPoint {}
 
-pub const PRIM_BUFFER_LEN: usize = 180;
+/// Primitives a buffer on the GPU has room for at first, it grows as entities do
+pub const PRIM_BUFFER_MIN_LEN: usize = 64;
+
+/// Blending for colors with their alpha already multiplied in, which every shader outputs
+pub(crate) const PREMULTIPLIED_BLEND: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+        operation: wgpu::BlendOperation::Add,
+    },
+    alpha: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+        operation: wgpu::BlendOperation::Add,
+    },
+};
+
+/// Entities with at least this many instances get their primitives as per-instance vertex
+/// attributes, which scale better than every vertex reading its primitive from storage
+pub const INSTANCED_MIN_LEN: usize = 256;
+
+/// Room for `len` primitives, rounded up so that an entity growing a little at a time
+/// doesn't need a new buffer every time
+pub fn prim_buffer_capacity(len: usize) -> usize {
+    len.max(PRIM_BUFFER_MIN_LEN).next_power_of_two()
+}
 
 /// This vertex constructor forwards the positions and normals provided by the
 /// tessellators and add a shape id.
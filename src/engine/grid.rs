@@ -0,0 +1,276 @@
+use crate::engine::gpu;
+use crate::tetrominos;
+use std::num::NonZeroU32;
+use wgpu::util::DeviceExt;
+
+/// Shades of a cell fill, as the high bits of its fill
+pub(crate) const SHADE_FULL: u8 = 0;
+pub(crate) const SHADE_GHOST: u8 = 1;
+pub(crate) const SHADE_HINT: u8 = 2;
+
+/// A grid cell as stored in the texture: the palette index and shade of the fill, and the
+/// palette index of an outline, 0 for none
+pub(crate) type Cell = [u8; 2];
+
+/// A cell filled with `block`, unknown blocks looking like garbage
+pub(crate) fn cell(block: usize, shade: u8) -> Cell {
+    let index = if block < tetrominos::Colors.len() { block } else { tetrominos::GARBAGE };
+    [index as u8 | shade << 4, 0]
+}
+
+/// Colors and sizes the grid is drawn with, laid out like the uniform of the grid shaders
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub(crate) struct GridPalette {
+    colors: [[f32; 4]; 16],
+    border_color: [f32; 4],
+    /// Brightness of each shade
+    shades: [f32; 4],
+    cell_size: f32,
+    border_width: f32,
+    outline_width: f32,
+    _pad: f32,
+}
+
+unsafe impl bytemuck::Pod for GridPalette {}
+unsafe impl bytemuck::Zeroable for GridPalette {}
+
+impl GridPalette {
+    /// The colors of the tetrominos, on black like the field blocks
+    pub(crate) fn new(cell_size: f32, border_width: f32, outline_width: f32) -> Self {
+        let mut colors = [tetrominos::Colors[tetrominos::GARBAGE]; 16];
+        for (color, block) in colors.iter_mut().zip(tetrominos::Colors.iter()) {
+            *color = *block;
+        }
+        colors[0] = [0.0, 0.0, 0.0, 1.0];
+        GridPalette {
+            colors,
+            border_color: [0.2, 0.2, 0.2, 1.0],
+            shades: [1.0, 0.2, 0.5, 1.0],
+            cell_size,
+            border_width,
+            outline_width,
+            _pad: 0.0,
+        }
+    }
+}
+
+/// Draws the play field in a single pass from a texture of its cells, as an alternative to
+/// a primitive per block
+pub(crate) struct GridRenderer {
+    cols: u32,
+    /// What the grid should show, uploaded on the next frame
+    pub(crate) cells: Vec<Cell>,
+    /// What the texture holds
+    uploaded: Vec<Cell>,
+    texture: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl GridRenderer {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        globals_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+        cols: u32,
+        rows: u32,
+        palette: &GridPalette,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Grid cells"),
+            size: wgpu::Extent3d {
+                width: cols,
+                height: rows,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rg8Uint,
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+        });
+
+        // Cells are only ever fetched, never filtered
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Grid sampler"),
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let palette_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Grid palette ubo"),
+            contents: bytemuck::cast_slice(&[*palette]),
+            usage: wgpu::BufferUsage::UNIFORM,
+        });
+
+        let visibility = wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT;
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Grid bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Uint,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility,
+                    ty: wgpu::BindingType::Sampler {
+                        filtering: false,
+                        comparison: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<GridPalette>() as u64),
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Grid bind group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Buffer(palette_buffer.as_entire_buffer_binding()),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[globals_bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+            label: None,
+        });
+
+        let vert = wgpu::include_spirv!(concat!(env!("OUT_DIR"), "/spirv/grid.vert.spv"));
+        let vert_shader = device.create_shader_module(&vert);
+        let frag = wgpu::include_spirv!(concat!(env!("OUT_DIR"), "/spirv/grid.frag.spv"));
+        let frag_shader = device.create_shader_module(&frag);
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Grid pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &vert_shader,
+                entry_point: "main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &frag_shader,
+                entry_point: "main",
+                targets: &[
+                    wgpu::ColorTargetState {
+                        format: wgpu::TextureFormat::Bgra8Unorm,
+                        blend: Some(gpu::PREMULTIPLIED_BLEND),
+                        write_mask: wgpu::ColorWrite::ALL,
+                    },
+                ],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                front_face: wgpu::FrontFace::Ccw,
+                strip_index_format: None,
+                cull_mode: None,
+                clamp_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+        });
+
+        // A new texture is all zeroes, which are empty cells
+        let len = (cols * rows) as usize;
+        GridRenderer {
+            cols,
+            cells: vec![[0, 0]; len],
+            uploaded: vec![[0, 0]; len],
+            texture,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    /// Writes the smallest rectangle holding every cell that changed since the last upload
+    pub(crate) fn upload(&mut self, queue: &wgpu::Queue) {
+        let cols = self.cols as usize;
+        let mut changed = self.cells.iter().zip(self.uploaded.iter())
+            .enumerate()
+            .filter(|(_, (cell, uploaded))| cell != uploaded)
+            .map(|(i, _)| (i % cols, i / cols));
+        let (col, row) = match changed.next() {
+            Some(first) => first,
+            None => return,
+        };
+        let (mut left, mut right, top, mut bottom) = (col, col, row, row);
+        for (col, row) in changed {
+            left = left.min(col);
+            right = right.max(col);
+            bottom = row;
+        }
+
+        let width = right - left + 1;
+        let height = bottom - top + 1;
+        let mut data = Vec::with_capacity(width * height * 2);
+        for row in top..=bottom {
+            for cell in &self.cells[row * cols + left..=row * cols + right] {
+                data.extend_from_slice(cell);
+            }
+        }
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: left as u32, y: top as u32, z: 0 },
+            },
+            &data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(width as u32 * 2),
+                rows_per_image: NonZeroU32::new(height as u32),
+            },
+            wgpu::Extent3d {
+                width: width as u32,
+                height: height as u32,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.uploaded.copy_from_slice(&self.cells);
+    }
+
+    pub(crate) fn render<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>, globals_bind_group: &'a wgpu::BindGroup) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, globals_bind_group, &[]);
+        pass.set_bind_group(1, &self.bind_group, &[]);
+        pass.draw(0..6, 0..1);
+    }
+}
@@ -0,0 +1,19 @@
+/// Layers of the scene, drawn from the back to the front with alpha blending
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Layer {
+    Background,
+    Field,
+    Pieces,
+    Effects,
+    Ui,
+}
+
+impl Layer {
+    pub const ALL: [Layer; 5] = [
+        Layer::Background,
+        Layer::Field,
+        Layer::Pieces,
+        Layer::Effects,
+        Layer::Ui,
+    ];
+}
@@ -2,33 +2,59 @@
 pub mod error;
 pub(crate) mod entities;
 pub mod gpu;
+pub(crate) mod grid;
+pub mod layer;
 
 const DEFAULT_WINDOW_WIDTH: f32 = 1024.0;
 const DEFAULT_WINDOW_HEIGHT: f32 = 768.0;
 
-const PLAYFIELD_COLS: u32 = 10;
-const PLAYFIELD_ROWS: u32 = 16;
-const PLAYFIELD_SIZE: u32 = PLAYFIELD_COLS * PLAYFIELD_ROWS;
 const TETRION_SIZE: f32 = 8.0;
+const BLOCK_STROKE_WIDTH: f32 = 0.3;
+const OUTLINE_WIDTH: f32 = 0.8;
 
-pub use entities::{BluePrint, GeoEntity, Entity, EntityToken};
+/// Instances of the board entity: the play field blocks, then the 4x4 of the current piece
+pub const BOARD_INSTANCES: usize = PLAYFIELD_SIZE as usize + 16;
+
+/// Instances of the outline entity: the ghost piece, then the target
+pub const OUTLINE_INSTANCES: usize = 8;
+
+/// Instances of the ghost board entity: the play field of the personal best being raced,
+/// its current piece drawn in
+pub const GHOST_BOARD_INSTANCES: usize = PLAYFIELD_SIZE as usize;
+
+/// The ghost board is drawn this far right of the board, faded to this alpha
+const GHOST_BOARD_OFFSET: f32 = (PLAYFIELD_COLS + 2) as f32 * TETRION_SIZE;
+const GHOST_ALPHA: f32 = 0.35;
+
+pub use entities::{BluePrint, GeoEntity, Entity, EntityToken, Instance, Instances, StrokeStyle};
+pub use layer::Layer;
 use lyon::math::{vector, size, point, Vector, Rect};
 use winit::event_loop as ELoop;
 use winit::window::Window;
 use futures::executor::block_on;
-use crate::engine::gpu::{Globals, Primitive, PRIM_BUFFER_LEN};
+use crate::engine::gpu::{Globals, Shading};
 use wgpu::{Device, SwapChainFrame};
 use winit::dpi::PhysicalSize;
 use crate::engine::entities::BgEntity;
+use crate::engine::grid::{GridPalette, GridRenderer};
 use lyon::path::traits::SvgPathBuilder;
 use lyon::path::Path;
+use std::ops::Range;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
 use std::time::{Duration, Instant};
 use winit::event::{VirtualKeyCode, Event, WindowEvent};
 use crate::engine::error::EngineError;
-use crate::tetrominos::TetroShape;
 use crate::tetrominos;
-use rand::rngs::ThreadRng;
-use rand::Rng;
+use crate::game::{FrameInput, Game, LockResult, PLAYFIELD_COLS, PLAYFIELD_ROWS, PLAYFIELD_SIZE, SPLIT_LINES};
+use crate::modes::{self, Mode, Outcome};
+use crate::fumen;
+use crate::rules::Scoring;
+use crate::scores::{self, GameResult, ScoreTable};
+use crate::solver::{Placement, Problem};
+use crate::history::History;
+use crate::save::SavedGame;
+use crate::replay::{Ghost, Replay};
 
 use log::{info, warn, error, debug};
 
@@ -39,7 +65,14 @@ pub struct Engine {
     frame_count: u32,
     anim_start: Instant,
     anim_secs: f32,
-    pub geo_entities: Vec<GeoEntity>,
+    /// Removed entities leave their slot empty, so that the index of every token stays valid
+    geo_entities: Vec<Option<GeoEntity>>,
+    /// The entity the play field and current piece are drawn with
+    board: Option<EntityToken<'static>>,
+    /// The entity the ghost piece and target are outlined with, if any
+    outline: Option<EntityToken<'static>>,
+    /// The entity the personal best being raced is replayed on, if any
+    ghost_board: Option<EntityToken<'static>>,
     bg_entities: Vec<BgEntity>,
     scene: SceneParams,
     pub device: wgpu::Device,
@@ -49,24 +82,66 @@ pub struct Engine {
     pub event_loop: Option<ELoop::EventLoop<()>>,
     swap_chain_desc: wgpu::SwapChainDescriptor,
     pub swap_chain: wgpu::SwapChain,
-    depth_texture_view: Option<wgpu::TextureView>,
     multisampled_render_target: Option<wgpu::TextureView>,
     queue: wgpu::Queue,
     pub surface: wgpu::Surface,
     pub window: Window,
     render_data: Option<RenderData>,
+    /// Draws the play field instead of the field blocks of the board, when enabled
+    gpu_grid: bool,
+    grid: Option<GridRenderer>,
+    fps: u32,
+    status: String,
+
+    pub game: Game,
+    modes: Vec<Mode>,
+    selected_mode: usize,
+    screen: Screen,
+    scores: Option<ScoreTable>,
+    /// Placements of a perfect clear, the first of which is shown while hints are on
+    hint: Option<Vec<Placement>>,
+    /// The perfect clear search running in the background, for the current piece
+    hint_search: Option<Receiver<Option<Vec<Placement>>>>,
+    /// Snapshots for undo and redo, kept in practice modes
+    history: History,
+    /// The selected game is a saved one, to be resumed when started
+    resuming: bool,
+    /// Inputs of the game being played, kept in modes that race the personal best
+    replay: Option<Replay>,
+    /// The personal best being raced, and its replay played alongside
+    pb: Option<GameResult>,
+    ghost: Option<Ghost>,
+    /// How far ahead (negative) or behind the personal best the last split was
+    split_delta: Option<f32>,
+}
 
-    pub playfield: [usize; PLAYFIELD_SIZE as usize],
-    pub rng: ThreadRng,
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Screen {
+    Select,
+    Playing,
+    /// Paused at `since`, counting down to `resume_at` once the player asks to resume
+    Paused { since: f32, resume_at: Option<f32> },
+    Finished(Outcome),
 }
 
+// Seconds of countdown before a paused game continues
+const RESUME_COUNTDOWN: f32 = 3.0;
+// Longest stretch of time simulated at once, after the window stalled
+const MAX_CATCH_UP_SECS: f32 = 0.25;
+// Most pieces the perfect clear hint looks ahead
+const HINT_MAX_PIECES: usize = 10;
+// Boards the perfect clear hint may visit. It is searched on a thread of its own, the limit
+// makes sure searches for pieces long gone don't pile up.
+const HINT_MAX_NODES: usize = 20000;
+
 pub(crate) struct RenderData {
     globals_buffer: wgpu::Buffer,
-    prim_buffers: Vec<wgpu::Buffer>,
-    pipeline_layout: wgpu::PipelineLayout,
-    bind_group: wgpu::BindGroup,
-    depth_stencil_state: Option<wgpu::DepthStencilState>,
-    sample_count: u32,
+    globals_bind_group: wgpu::BindGroup,
+    prims_bind_group_layout: wgpu::BindGroupLayout,
+    /// Shared by all entities of a kind, so that adding one only takes buffers
+    geo_pipeline: wgpu::RenderPipeline,
+    geo_instanced_pipeline: wgpu::RenderPipeline,
+    bg_pipeline: wgpu::RenderPipeline,
 }
 
 fn test(s: impl SvgPathBuilder) {
@@ -121,15 +196,25 @@ impl Engine {
             window_size: PhysicalSize::new(DEFAULT_WINDOW_WIDTH as u32, DEFAULT_WINDOW_HEIGHT as u32),
             size_changed: true,
             target_piece_x: 0,
-            target_piece_y: 0,
-            target_tet_rot: 0,
-            curr_tet_index: 0,
-            curr_tet_pos: [0, 0],
-            curr_tet_active: false,
-            curr_tet_rot: 0,
-            curr_tet_matrix: TetroShape::Odd(tetrominos::TETRO_NONE),
+            rotate: 0,
+            hard_drop: false,
+            hold: false,
+            menu_delta: 0,
+            confirm: false,
+            dump_fumen: false,
+            show_stats: true,
+            show_hint: false,
+            toggle_hint: false,
+            history_step: 0,
+            held_keys: Vec::new(),
+            key_presses: 0,
+            finesse_presses: 0,
+            pause: false,
+            focus_lost: false,
+            shift: 0,
             speed: 1.0,
-            last_down_secs: 0.0,
+            last_frame_secs: 0.0,
+            frame_acc: 0.0,
         };
 
         // create a device and a queue
@@ -167,6 +252,9 @@ impl Engine {
             anim_start,
             bg_entities: Vec::new(),
             geo_entities: Vec::new(),
+            board: None,
+            outline: None,
+            ghost_board: None,
             scene,
             device,
             sample_count,
@@ -174,14 +262,28 @@ impl Engine {
             event_loop: Some(event_loop),
             swap_chain,
             swap_chain_desc,
-            depth_texture_view: None,
             multisampled_render_target: None,
             queue,
             surface,
             window,
             render_data: None,
-            playfield: [ 0 ; PLAYFIELD_SIZE as usize],
-            rng: rand::thread_rng()
+            gpu_grid: false,
+            grid: None,
+            fps: 0,
+            status: String::new(),
+            game: Game::new(),
+            modes: Vec::new(),
+            selected_mode: 0,
+            screen: Screen::Select,
+            scores: None,
+            hint: None,
+            hint_search: None,
+            history: History::default(),
+            resuming: false,
+            replay: None,
+            pb: None,
+            ghost: None,
+            split_delta: None,
         };
         engine
     }
@@ -192,7 +294,10 @@ impl Engine {
             point(-(dim / 2.0), -(dim / 2.0)),
             size(dim, dim));
         match eb.build_bg(rect) {
-            Ok(entity) => {
+            Ok(mut entity) => {
+                if self.render_data.is_some() {
+                    entity.init_render(&self.device);
+                }
                 self.bg_entities.push(entity);
                 // let index = self.bg_entities.len()-1;
                 Ok(())
@@ -212,18 +317,91 @@ impl Engine {
     //     return self.add_entity(e)
     // }
 
-    pub fn create_geo_entity(&mut self, path: &Path, instances: usize, fill: bool, stroke: bool, scale: f32) -> EngineResult<EntityToken> {
+    /// An entity of `path`, outlined if given a `stroke` style
+    pub fn create_geo_entity(&mut self, path: &Path, instances: usize, fill: bool, stroke: Option<StrokeStyle>, scale: f32) -> EngineResult<EntityToken<'static>> {
+        if let Some(style) = &stroke {
+            style.validate().map_err(EngineError::InvalidStrokeStyle)?;
+        }
         let e = entities::EntityBuilder::new()
-            .build_geo(&path, instances, scale, fill, stroke)?;
+            .build_geo(&path, instances, scale, fill, stroke.as_ref())?;
         self.add_entity(e)
     }
 
-    pub fn add_entity(&mut self, e: GeoEntity) -> EngineResult<EntityToken> {
-        self.geo_entities.push(e);
-        let index = self.geo_entities.len()-1;
-        Ok(EntityToken::new(index, &self.geo_entities[index]))
+    pub fn add_entity(&mut self, mut e: GeoEntity) -> EngineResult<EntityToken<'static>> {
+        if let Some(render_data) = &self.render_data {
+            e.init_render(&self.device, render_data);
+        }
+        let token = EntityToken::new(self.geo_entities.len(), &e);
+        self.geo_entities.push(Some(e));
+        Ok(token)
+    }
+
+    /// Removes the entity of `token`, freeing its buffers on the GPU
+    pub fn remove_entity(&mut self, token: &EntityToken) -> Option<GeoEntity> {
+        self.geo_entities.get_mut(token.index)?.take()
+    }
+
+    pub fn entity(&mut self, token: &EntityToken) -> EngineResult<&mut GeoEntity> {
+        self.geo_entities.get_mut(token.index)
+            .and_then(|slot| slot.as_mut())
+            .ok_or(EngineError::NoSuchEntity(token.index))
+    }
+
+    pub fn instance(&mut self, token: &EntityToken, index: usize) -> EngineResult<Instance<'_>> {
+        self.entity(token)?.instance(index)
+    }
+
+    pub fn instance_range(&mut self, token: &EntityToken, range: Range<usize>) -> EngineResult<Instances<'_>> {
+        self.entity(token)?.instance_range(range)
+    }
+
+    pub fn alloc_instance(&mut self, token: &EntityToken) -> EngineResult<usize> {
+        Ok(self.entity(token)?.alloc_instance())
+    }
+
+    pub fn free_instance(&mut self, token: &EntityToken, index: usize) -> EngineResult<()> {
+        self.entity(token)?.free_instance(index)
+    }
+
+    /// Draws the play field and current piece with the entity of `token`, which needs
+    /// at least `BOARD_INSTANCES` instances
+    pub fn set_board(&mut self, token: EntityToken<'static>) -> EngineResult<()> {
+        let instances = self.entity(&token)?.instance_count();
+        if instances < BOARD_INSTANCES {
+            return Err(EngineError::InstanceOutOfRange { index: BOARD_INSTANCES - 1, instances });
+        }
+        self.board = Some(token);
+        Ok(())
+    }
+
+    /// Outlines the ghost piece and target with the entity of `token`, which needs at
+    /// least `OUTLINE_INSTANCES` instances. Without one they are drawn on the board.
+    pub fn set_outline(&mut self, token: EntityToken<'static>) -> EngineResult<()> {
+        let instances = self.entity(&token)?.instance_count();
+        if instances < OUTLINE_INSTANCES {
+            return Err(EngineError::InstanceOutOfRange { index: OUTLINE_INSTANCES - 1, instances });
+        }
+        self.outline = Some(token);
+        Ok(())
+    }
+
+    /// Replays the personal best being raced with the entity of `token`, which needs at
+    /// least `GHOST_BOARD_INSTANCES` instances. Without one the ghost is only in the title.
+    pub fn set_ghost_board(&mut self, token: EntityToken<'static>) -> EngineResult<()> {
+        let instances = self.entity(&token)?.instance_count();
+        if instances < GHOST_BOARD_INSTANCES {
+            return Err(EngineError::InstanceOutOfRange { index: GHOST_BOARD_INSTANCES - 1, instances });
+        }
+        self.ghost_board = Some(token);
+        Ok(())
     }
     
+    /// Draws the play field from a texture of its cells, in one pass. Takes effect with
+    /// `init_render`.
+    pub fn set_gpu_grid(&mut self, enabled: bool) {
+        self.gpu_grid = enabled;
+    }
+
     pub fn init_render(&mut self) {
         info!("Initializing GPU buffers...");
         let device = &self.device;
@@ -236,108 +414,89 @@ impl Engine {
             mapped_at_creation: false,
         });
 
-        let mut bind_layouts = vec![wgpu::BindGroupLayoutEntry {
-            binding: 0,
-            visibility: wgpu::ShaderStage::VERTEX,
-            ty: wgpu::BindingType::Buffer {
-                ty: wgpu::BufferBindingType::Uniform,
-                has_dynamic_offset: false,
-                min_binding_size: wgpu::BufferSize::new(globals_buffer_byte_size),
-            },
-            count: None,
-        }];
-        let mut bind_groups = vec![wgpu::BindGroupEntry {
-            binding: 0,
-            resource: wgpu::BindingResource::Buffer(globals_buffer.as_entire_buffer_binding()),
-        }];
-        
-        let mut prim_buffers: Vec<wgpu::Buffer> = Vec::new();
-        let prim_bind_offset = 1;
-        
-        for (i, ge) in self.geo_entities.iter().enumerate() {
-
-            let binding = (prim_bind_offset + i) as u32;
-            let pbuf_byte_size = (PRIM_BUFFER_LEN * std::mem::size_of::<Primitive>()) as u64;
-            
-            let pbuf = device.create_buffer(&wgpu::BufferDescriptor {
-                label: Some("Prims ubo"),
-                size: pbuf_byte_size,
-                usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
-                mapped_at_creation: false,
-            });
-
-            prim_buffers.push(pbuf);
-            // let pbuf2 = prim_buffers.get(i).unwrap();
-            
-
-
-            
-            bind_layouts.push(wgpu::BindGroupLayoutEntry{
-                binding,
+        let globals_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Globals bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
                 visibility: wgpu::ShaderStage::VERTEX,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Uniform,
                     has_dynamic_offset: false,
-                    min_binding_size: wgpu::BufferSize::new(pbuf_byte_size),
+                    min_binding_size: wgpu::BufferSize::new(globals_buffer_byte_size),
                 },
                 count: None,
-            });
-
-
-        }
-
-        for (i, pb) in prim_buffers.iter().enumerate() {
-            bind_groups.push(wgpu::BindGroupEntry {
-                binding: (prim_bind_offset + i) as u32,
-                resource: wgpu::BindingResource::Buffer(pb.as_entire_buffer_binding()),
-            });
-        }
+            }],
+        });
 
-        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Bind group layout"),
-            entries: &bind_layouts[..]
+        // Every geo entity binds its own primitives as the second group
+        let prims_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Prims bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
         });
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            bind_group_layouts: &[&bind_group_layout],
+            bind_group_layouts: &[&globals_bind_group_layout, &prims_bind_group_layout],
             push_constant_ranges: &[],
             label: None,
         });
-        
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Bind group"),
-            layout: &bind_group_layout,
-            entries: &bind_groups[..],
+
+        // The background and instanced geo entities only use the globals
+        let globals_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[&globals_bind_group_layout],
+            push_constant_ranges: &[],
+            label: None,
         });
 
-        let depth_stencil_state = Some(wgpu::DepthStencilState {
-            format: wgpu::TextureFormat::Depth32Float,
-            depth_write_enabled: true,
-            depth_compare: wgpu::CompareFunction::Greater,
-            stencil: wgpu::StencilState {
-                front: wgpu::StencilFaceState::IGNORE,
-                back: wgpu::StencilFaceState::IGNORE,
-                read_mask: 0,
-                write_mask: 0,
-            },
-            bias: wgpu::DepthBiasState::default(),
+        let globals_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Globals bind group"),
+            layout: &globals_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(globals_buffer.as_entire_buffer_binding()),
+            }],
         });
 
+        let geo_pipeline = entities::geo_entity::create_pipeline(
+            device, &pipeline_layout, self.sample_count, false);
+        let geo_instanced_pipeline = entities::geo_entity::create_pipeline(
+            device, &globals_pipeline_layout, self.sample_count, true);
+        let bg_pipeline = entities::bg_entity::create_pipeline(
+            device, &globals_pipeline_layout, self.sample_count);
+
+        self.grid = if self.gpu_grid {
+            let palette = GridPalette::new(TETRION_SIZE, BLOCK_STROKE_WIDTH, OUTLINE_WIDTH);
+            Some(GridRenderer::new(
+                device, &globals_bind_group_layout, self.sample_count,
+                PLAYFIELD_COLS, PLAYFIELD_ROWS, &palette))
+        } else {
+            None
+        };
+
         let render_data = RenderData{
             globals_buffer,
-            prim_buffers,
-            pipeline_layout,
-            bind_group,
-            depth_stencil_state,
-            sample_count: self.sample_count,
+            globals_bind_group,
+            prims_bind_group_layout,
+            geo_pipeline,
+            geo_instanced_pipeline,
+            bg_pipeline,
         };
 
-        for geo in self.geo_entities.iter_mut() {
+        // Entities added from now on are initialized as they come
+        for geo in self.geo_entities.iter_mut().flatten() {
             geo.init_render(device, &render_data);
         }
 
         for bg in self.bg_entities.iter_mut() {
-            bg.init_render(device, &render_data);
+            bg.init_render(device);
         }
 
         self.render_data = Some(render_data);
@@ -354,33 +513,459 @@ impl Engine {
     // }
 
     pub fn init_game(&mut self) {
+        self.modes = modes::load_modes();
+        for (i, mode) in self.modes.iter().enumerate() {
+            info!("Mode {}: {}", i + 1, mode.name());
+        }
+
+        self.scores = match ScoreTable::load() {
+            Ok(table) => Some(table),
+            Err(err) => {
+                // Don't risk overwriting a score file we don't understand
+                warn!("Failed to load scores, results will not be saved: {}", err);
+                None
+            }
+        };
+
+        // Lay out the play field blocks, the field and current piece shaded alike
+        let board = board_entity(&mut self.geo_entities, &self.board)
+            .map(|board| board.instance_range(0..BOARD_INSTANCES));
+        if let Some(Ok(mut board)) = board {
+            board.set_shading(Shading::Gradient);
+        }
+        let field = board_entity(&mut self.geo_entities, &self.board)
+            .map(|board| board.instance_range(0..PLAYFIELD_SIZE as usize));
+        // The GPU grid draws borders of its own, otherwise the shader animates the outlines
+        // of the empty cells
+        let gpu_grid = self.grid.is_some();
+        if let Some(Ok(mut field)) = field {
+            field.for_each(|idx, block| {
+                let col = idx % PLAYFIELD_COLS as usize;
+                let row = idx / PLAYFIELD_COLS as usize;
+                block.set_translate([col as f32 * TETRION_SIZE, row as f32 * TETRION_SIZE])
+                    .set_width(BLOCK_STROKE_WIDTH);
+                if !gpu_grid {
+                    block.set_stroke_color([0.0, 0.0, 0.0, 1.0])
+                        .set_wave([col as f32 / PLAYFIELD_COLS as f32, row as f32 / PLAYFIELD_ROWS as f32]);
+                }
+            });
+        }
+
+        // The ghost board sits right of the board, hidden until there is a ghost to race
+        let ghost_board = board_entity(&mut self.geo_entities, &self.ghost_board)
+            .map(|board| board.instance_range(0..GHOST_BOARD_INSTANCES));
+        if let Some(Ok(mut ghost_board)) = ghost_board {
+            ghost_board.for_each(|idx, block| {
+                let col = idx % PLAYFIELD_COLS as usize;
+                let row = idx / PLAYFIELD_COLS as usize;
+                block.set_translate([GHOST_BOARD_OFFSET + col as f32 * TETRION_SIZE, row as f32 * TETRION_SIZE])
+                    .set_width(BLOCK_STROKE_WIDTH)
+                    .set_shading(Shading::Gradient)
+                    .hide();
+            });
+        }
+
+        self.select_mode(0);
+        self.offer_saved_game();
+    }
+
+    /// Selects the game left in progress last time, if there is one
+    fn offer_saved_game(&mut self) {
+        let saved = match SavedGame::load() {
+            Ok(Some(saved)) => saved,
+            Ok(None) => return,
+            Err(err) => {
+                warn!("Failed to load the saved game: {}", err);
+                return;
+            }
+        };
+        match self.modes.iter().position(|mode| mode.name() == saved.mode) {
+            Some(index) => {
+                self.select_mode(index);
+                self.game = saved.game;
+                self.resuming = true;
+                self.update_field();
+                self.update_tet();
+                self.set_status(format!("{} (saved game) - Return: resume, Up/Down: choose another", saved.mode));
+            }
+            None => warn!("Saved game is for an unknown mode {:?}", saved.mode),
+        }
+    }
+
+    /// Saves the game in progress, to be offered again on the next launch
+    fn save_on_exit(&self) {
+        if !matches!(self.screen, Screen::Playing | Screen::Paused { .. }) || self.game.over {
+            return;
+        }
+        match SavedGame::new(self.modes[self.selected_mode].name(), &self.game).save() {
+            Ok(()) => info!("Saved the game in progress"),
+            Err(err) => warn!("Failed to save the game: {}", err),
+        }
+    }
+
+    /// Adds a mode in front of the loaded ones and selects it
+    pub fn add_mode(&mut self, mode: Mode) {
+        self.modes.insert(0, mode);
+        self.select_mode(0);
+    }
+
+    fn select_mode(&mut self, index: usize) {
+        self.selected_mode = index;
+        self.screen = Screen::Select;
+        self.resuming = false;
+        // Show the starting board of the mode as a preview
+        self.game = self.modes[index].new_game();
+        self.clear_hint();
+        self.history.clear();
+        self.update_field();
+        self.update_tet();
+        self.set_status(format!("{} ({}/{}) - Up/Down: choose, Return: start",
+                                self.modes[index].name(), index + 1, self.modes.len()));
+    }
+
+    fn start_game(&mut self) {
+        let mode = &self.modes[self.selected_mode];
+        info!("Starting {}: {}", mode.name(), mode.description());
+        // A resumed game can't be replayed from the start
+        let race = mode.races_pb() && !self.resuming;
+        self.replay = if race { Some(Replay::default()) } else { None };
+        self.pb = match &self.scores {
+            Some(table) if race => table.best(mode.name(), mode.ranking()).cloned(),
+            _ => None,
+        };
+        self.ghost = self.pb.as_ref().and_then(|pb| Ghost::new(mode, pb));
+        self.split_delta = None;
+        if self.resuming {
+            self.resuming = false;
+            self.scene.speed = self.game.speed;
+        }
+        // Starting any game uses up the saved one, whether it was resumed or passed over
+        if let Err(err) = SavedGame::remove() {
+            warn!("Failed to remove the saved game: {}", err);
+        }
+        self.screen = Screen::Playing;
+        self.scene.last_frame_secs = self.anim_secs;
+        self.scene.frame_acc = 0.0;
+        self.scene.key_presses = 0;
+        self.scene.finesse_presses = 0;
+        self.set_status(self.modes[self.selected_mode].name().to_string());
+    }
+
+    fn finish_game(&mut self, outcome: Outcome) {
+        let name = self.modes[self.selected_mode].name();
+        let message = match outcome {
+            Outcome::Success => format!("{}: Success!", name),
+            Outcome::Failure => format!("{}: Game over", name),
+        };
+        info!("{} ({} lines, {} points in {})", message, self.game.lines, self.game.score,
+              scores::format_time(self.game.time));
+        self.screen = Screen::Finished(outcome);
+        let rank = self.record_result(outcome);
+        self.print_summary(outcome, rank);
+        let pb = if rank == Some(0) { " NEW PERSONAL BEST!" } else { "" };
+        self.set_status(format!("{}{} - Return: continue", message, pb));
+    }
+
+    /// Adds the finished game to the score table. Returns its rank in the mode, if it
+    /// made the top.
+    fn record_result(&mut self, outcome: Outcome) -> Option<usize> {
+        let mode = &self.modes[self.selected_mode];
+        let table = match &mut self.scores {
+            Some(table) if mode.records(outcome) => table,
+            _ => return None,
+        };
+        let mut result = GameResult {
+            mode: mode.name().to_string(),
+            score: self.game.score,
+            lines: self.game.lines,
+            time_secs: self.game.time,
+            date: scores::now(),
+            seed: self.game.seed,
+            replay_file: None,
+            splits: if mode.races_pb() { self.game.line_splits.clone() } else { Vec::new() },
+        };
+        if let Some(replay) = &self.replay {
+            let saved = scores::get_replay_file(result.date, result.seed)
+                .and_then(|file| replay.save(&file).map(|()| file));
+            match saved {
+                Ok(file) => result.replay_file = Some(file),
+                Err(err) => warn!("Failed to save the replay: {}", err),
+            }
+        }
+        let rank = table.add(result, mode.ranking());
+        if let Err(err) = table.save() {
+            warn!("Failed to save scores: {}", err);
+        }
+        rank
+    }
+
+    /// Prints the stats of the finished game to the terminal, then the top 10 of the mode
+    /// with the game at `rank` highlighted, if it was recorded. This is the only output of
+    /// the engine that isn't logged, as it is meant to be read after playing.
+    fn print_summary(&self, outcome: Outcome, rank: Option<usize>) {
+        let mode = &self.modes[self.selected_mode];
+        println!();
+        print!("{}", self.game.stats.format_summary(self.game.time));
+        if self.game.rules.scoring == Scoring::Tgm {
+            println!("  Grade:       {} (level {})", self.game.grading.grade(self.game.score), self.game.level);
+        }
+        for (i, split) in self.game.garbage_splits.iter().enumerate() {
+            println!("  Garbage {:>3}: {}", i + 1, scores::format_time(*split));
+        }
+        if mode.races_pb() {
+            for (i, split) in self.game.line_splits.iter().enumerate() {
+                println!("  Split   {:>3}: {}", (i as u32 + 1) * SPLIT_LINES, scores::format_time(*split));
+            }
+        }
+        if let (Some(table), true) = (&self.scores, mode.records(outcome)) {
+            println!();
+            print!("{}", table.format_top(mode.name(), mode.ranking(), rank));
+        }
+    }
+
+    fn set_status(&mut self, status: String) {
+        self.status = status;
+        self.update_title();
+    }
+
+    fn update_title(&self) {
+        let mut info = String::new();
+        if self.screen == Screen::Playing {
+            let next: Vec<&str> = self.game.previews().map(|&t| tetrominos::NAMES[t]).collect();
+            if !next.is_empty() {
+                info += &format!(" | Next: {}", next.join(" "));
+            }
+            match self.game.rules.scoring {
+                Scoring::Nes => info += &format!(" | Level {} | Score {}", self.game.level, self.game.score),
+                Scoring::Tgm => info += &format!(" | Level {} | Grade {} | Score {}", self.game.level,
+                                                 self.game.grading.grade(self.game.score), self.game.score),
+                Scoring::Guideline => {}
+            }
+            if self.scene.show_stats {
+                info += &format!(" | {}", self.game.stats.format_hud(self.game.time));
+            }
+            if let Some(ghost) = &self.ghost {
+                info += &format!(" | PB ghost: {} lines", ghost.game.lines);
+            }
+            match (&self.hint, self.scene.show_hint) {
+                (Some(hint), true) if hint.first().is_some_and(|p| p.hold) => info += &format!(" | PC in {} (hold first)", hint.len()),
+                (Some(hint), true) => info += &format!(" | PC in {}", hint.len()),
+                (None, true) if self.hint_search.is_some() => info += " | Looking for a PC",
+                (None, true) => info += " | No PC in sight",
+                (_, false) => {}
+            }
+        }
+        self.window.set_title(&*format!("Ruzzle [{:.1}x] {} FPS - {}{}",
+                                        self.scene.speed,
+                                        self.fps,
+                                        self.status,
+                                        info));
+    }
+
+    /// The board is kept out of sight while paused, so pausing can't be used to plan ahead
+    fn is_board_hidden(&self) -> bool {
+        matches!(self.screen, Screen::Paused { resume_at: None, .. })
+    }
 
+    /// Updates the play field blocks from the game state
+    fn update_field(&mut self) {
+        self.update_outline();
+        self.update_ghost_board();
+        if self.grid.is_some() {
+            return self.update_grid();
+        }
+        let hidden = self.is_board_hidden();
+        let board = match board_entity(&mut self.geo_entities, &self.board) {
+            Some(board) => board,
+            None => return,
+        };
+        let playfield = &self.game.playfield;
+        if let Ok(mut field) = board.instance_range(0..PLAYFIELD_SIZE as usize) {
+            field.set_width(BLOCK_STROKE_WIDTH)
+                .set_colors(playfield.iter().map(|&block| if block == 0 || hidden {
+                    [0.0, 0.0, 0.0, 1.0]
+                } else {
+                    tetrominos::block_color(block)
+                }));
+        }
+        let outlined = self.outline.is_some();
+        if let (Some(ghost), true, false, false) = (self.game.ghost(), self.game.rules.ghost, hidden, outlined) {
+            let [r, g, b, a] = tetrominos::Colors[ghost.index];
+            for cell in ghost.cells().into_iter().filter(|[_, row]| *row >= 0) {
+                if let Ok(mut block) = board.instance(cell_index(cell)) {
+                    block.set_color([r * 0.2, g * 0.2, b * 0.2, a]);
+                }
+            }
+        }
+        // Without an outline entity, targets are outlined with the stroke of the field blocks
+        if let (Some(target), Some(piece), false, false) = (&self.game.target, &self.game.piece, hidden, outlined) {
+            for &cell in target {
+                if let Ok(mut block) = board.instance(cell_index(cell)) {
+                    block.set_stroke_color(tetrominos::Colors[piece.index])
+                        .set_width(OUTLINE_WIDTH);
+                }
+            }
+        }
+        if let (Some(Placement { piece, .. }), true, false) =
+            (self.hint.as_ref().and_then(|h| h.first()), self.scene.show_hint, hidden)
+        {
+            let [r, g, b, a] = tetrominos::Colors[piece.index];
+            for cell in piece.cells() {
+                if let Ok(mut block) = board.instance(cell_index(cell)) {
+                    block.set_color([r * 0.5, g * 0.5, b * 0.5, a]);
+                }
+            }
+        }
+    }
+
+    /// Draws the play field and piece of the ghost, faded. The empty cells are outlined
+    /// green when ahead of the personal best at the last split, red when behind.
+    fn update_ghost_board(&mut self) {
+        let shown = self.screen != Screen::Select && !self.is_board_hidden();
+        let ghost = match (&self.ghost, shown) {
+            (Some(ghost), true) => Some(&ghost.game),
+            _ => None,
+        };
+        let mut blocks = ghost.map(|game| game.playfield);
+        if let (Some(blocks), Some(piece)) = (&mut blocks, ghost.and_then(|game| game.piece.as_ref())) {
+            for cell in piece.cells() {
+                if let Some(block) = blocks.get_mut(cell_index(cell)) {
+                    *block = piece.index;
+                }
+            }
+        }
+        let stroke = match self.split_delta {
+            Some(delta) if delta <= 0.0 => [0.2, 0.8, 0.2, GHOST_ALPHA],
+            Some(_) => [0.9, 0.2, 0.2, GHOST_ALPHA],
+            None => [0.5, 0.5, 0.5, GHOST_ALPHA],
+        };
+        let ghost_board = match board_entity(&mut self.geo_entities, &self.ghost_board) {
+            Some(ghost_board) => ghost_board,
+            None => return,
+        };
+        if let Ok(mut field) = ghost_board.instance_range(0..GHOST_BOARD_INSTANCES) {
+            field.for_each(|idx, block| match blocks.as_ref().map(|blocks| blocks[idx]) {
+                Some(0) => {
+                    block.set_color([0.0, 0.0, 0.0, 0.0])
+                        .set_stroke_color(stroke);
+                }
+                Some(index) => {
+                    let [r, g, b, _] = tetrominos::block_color(index);
+                    block.set_color([r, g, b, GHOST_ALPHA])
+                        .set_stroke_color([0.0, 0.0, 0.0, GHOST_ALPHA]);
+                }
+                None => {
+                    block.hide();
+                }
+            });
+        }
+    }
+
+    /// Moves the outlines onto the cells of the ghost piece and target. The GPU grid draws
+    /// both itself, so the outlines stay hidden with it.
+    fn update_outline(&mut self) {
+        let shown = self.grid.is_none() && !self.is_board_hidden();
+        let ghost = self.game.ghost().filter(|_| self.game.rules.ghost)
+            .map(|ghost| (ghost.cells(), ghost.index));
+        let target = self.game.target.clone().zip(self.game.piece.as_ref().map(|piece| piece.index));
+        // Four outlines for each, the ones not needed are hidden
+        let mut cells = [None; OUTLINE_INSTANCES];
+        for (first, outlined) in [(0, ghost), (4, target)].iter() {
+            if let (Some((piece_cells, index)), true) = (outlined, shown) {
+                let piece_cells = piece_cells.iter().filter(|&&[col, row]| col >= 0 && row >= 0);
+                for (cell, &[col, row]) in cells[*first..first + 4].iter_mut().zip(piece_cells) {
+                    *cell = Some(([col as f32 * TETRION_SIZE, row as f32 * TETRION_SIZE], *index));
+                }
+            }
+        }
+        let outline = match board_entity(&mut self.geo_entities, &self.outline) {
+            Some(outline) => outline,
+            None => return,
+        };
+        if let Ok(mut outlines) = outline.instance_range(0..OUTLINE_INSTANCES) {
+            outlines.for_each(|i, outline| match cells[i] {
+                Some((translate, index)) => {
+                    outline.set_translate(translate)
+                        .set_stroke_color(tetrominos::Colors[index])
+                        .set_width(OUTLINE_WIDTH);
+                }
+                None => {
+                    outline.hide();
+                }
+            });
+        }
+    }
+
+    /// Updates the cells of the GPU grid from the game state, hiding the field blocks it
+    /// replaces
+    fn update_grid(&mut self) {
+        let hidden = self.is_board_hidden();
+        let field = board_entity(&mut self.geo_entities, &self.board)
+            .map(|board| board.instance_range(0..PLAYFIELD_SIZE as usize));
+        if let Some(Ok(mut field)) = field {
+            field.hide();
+        }
+        let cells = match &mut self.grid {
+            Some(grid) => &mut grid.cells,
+            None => return,
+        };
+        for (cell, &block) in cells.iter_mut().zip(self.game.playfield.iter()) {
+            *cell = grid::cell(if hidden { 0 } else { block }, grid::SHADE_FULL);
+        }
+        if hidden {
+            return;
+        }
+        if let (Some(ghost), true) = (self.game.ghost(), self.game.rules.ghost) {
+            for c in ghost.cells() {
+                if let Some(cell) = cells.get_mut(cell_index(c)) {
+                    *cell = grid::cell(ghost.index, grid::SHADE_GHOST);
+                }
+            }
+        }
+        if let (Some(target), Some(piece)) = (&self.game.target, &self.game.piece) {
+            for &c in target {
+                if let Some(cell) = cells.get_mut(cell_index(c)) {
+                    cell[1] = piece.index as u8;
+                }
+            }
+        }
+        if let (Some(Placement { piece, .. }), true) =
+            (self.hint.as_ref().and_then(|h| h.first()), self.scene.show_hint)
+        {
+            for c in piece.cells() {
+                if let Some(cell) = cells.get_mut(cell_index(c)) {
+                    *cell = grid::cell(piece.index, grid::SHADE_HINT);
+                }
+            }
+        }
     }
 
     fn update_tet(&mut self) {
-        let current_tet_index = self.scene.curr_tet_index as usize;
-        let curr_tet_matrix = &self.scene.curr_tet_matrix;
-        let curr_pos = &self.scene.curr_tet_pos;
-        for geo in self.geo_entities.iter_mut() {
-            for r in 0..4 as usize {
-                for c in 0..4 as usize {
-                    let mut prim = &mut geo.primitives[PLAYFIELD_SIZE as usize + (r * 4) + c];
-                    let solid = curr_tet_matrix.is_solid(c, r);
-                    prim.translate = [
-                        (curr_pos[0] as f32 + c as f32) * TETRION_SIZE,
-                        (curr_pos[1] as f32 + r as f32) * TETRION_SIZE];
-                    prim.scale = geo.scale;
-                    if solid {
-                        prim.color_stroke = [1.0, 1.0, 1.0, 1.0];
-                        prim.color = tetrominos::Colors[current_tet_index];
-                        prim.width = 0.3;
-                        prim.z_index = PLAYFIELD_SIZE as i32;
-                    } else {
-                        prim.color_stroke = [1.0, 0.0, 1.0, 0.0];
-                        prim.color = [1.0, 0.0, 1.0, 0.0];
+        let piece = if self.is_board_hidden() { None } else { self.game.piece.as_ref() };
+        let board = match board_entity(&mut self.geo_entities, &self.board) {
+            Some(board) => board,
+            None => return,
+        };
+        let scale = board.scale;
+        if let Ok(mut tet) = board.instance_range(PLAYFIELD_SIZE as usize..BOARD_INSTANCES) {
+            tet.for_each(|i, block| {
+                let (r, c) = (i / 4, i % 4);
+                block.set_scale(scale);
+                match piece {
+                    // Parts of the piece above the playfield aren't shown
+                    Some(piece) if piece.matrix.is_solid(c, r) && piece.pos[1] + r as i32 >= 0 => {
+                        block.set_translate([
+                                (piece.pos[0] as f32 + c as f32) * TETRION_SIZE,
+                                (piece.pos[1] as f32 + r as f32) * TETRION_SIZE])
+                            .set_stroke_color([1.0, 1.0, 1.0, 1.0])
+                            .set_color(tetrominos::Colors[piece.index])
+                            .set_width(BLOCK_STROKE_WIDTH);
+                    }
+                    _ => {
+                        block.hide();
                     }
                 }
-            }
+            });
         }
     }
 
@@ -405,9 +990,8 @@ impl Engine {
                 self.anim_secs = (now - self.anim_start).as_secs_f32();
                 if now >= self.next_report {
                     // println!("{} FPS", frame_count);
-                    self.window.set_title(&*format!("Ruzzle [{:.1}x] {} FPS",
-                                                    self.scene.speed,
-                                                    self.frame_count));
+                    self.fps = self.frame_count;
+                    self.update_title();
                     self.frame_count = 0;
                     self.next_report = now + Duration::from_secs(1);
                 }
@@ -427,23 +1011,6 @@ impl Engine {
 
         warn!("Size changed! New size: {}, {} Updating surface...", physical.width, physical.height);
 
-        let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Depth texture"),
-            size: wgpu::Extent3d {
-                width: swap_chain_desc.width,
-                height: swap_chain_desc.height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: self.sample_count,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Depth32Float,
-            usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
-        });
-
-        self.depth_texture_view =
-            Some(depth_texture.create_view(&wgpu::TextureViewDescriptor::default()));
-
         self.multisampled_render_target = if self.sample_count > 1 {
             Some(create_multisampled_framebuffer(
                 &device,
@@ -470,125 +1037,277 @@ impl Engine {
     }
 
     fn update_state(&mut self) {
-
-        let num_instances = PLAYFIELD_SIZE as usize;
-        let fill_prim_id = 0;
-
         let time_secs = self.anim_secs;
 
-        if !self.scene.curr_tet_active {
-            self.scene.curr_tet_index = self.rng.gen_range(1..tetrominos::TL as u8);
-            self.scene.curr_tet_pos = [0, 0];
-            self.scene.curr_tet_rot = 0;
-            self.scene.curr_tet_matrix = tetrominos::ALL[self.scene.curr_tet_index as usize].clone();
-            info!("New tetromino: {:?}", tetrominos::NAMES[self.scene.curr_tet_index as usize]);
-            self.update_tet();
-            self.scene.curr_tet_active = true;
+        if self.scene.dump_fumen {
+            self.scene.dump_fumen = false;
+            let page = fumen::Page::from_game(&self.game);
+            info!("Current board: {}", fumen::encode(&[page]));
         }
 
-        if self.scene.curr_tet_rot != self.scene.target_tet_rot {
-            let curr_tet_shape = &tetrominos::ALL[self.scene.curr_tet_index as usize];
-            let new_matrix = curr_tet_shape.rotated(self.scene.target_tet_rot);
-            if check_if_free(self.scene.curr_tet_pos, &new_matrix, &self.playfield) {
-                self.scene.curr_tet_matrix = new_matrix;
-                self.update_tet();
-                self.scene.curr_tet_rot = self.scene.target_tet_rot;
-            } else {
-                // NO! BAD PLAYER
-                self.scene.target_tet_rot = self.scene.curr_tet_rot;
+        self.poll_hint();
+        let pause = self.scene.pause || self.scene.focus_lost;
+        match self.screen {
+            Screen::Select => self.update_select(),
+            Screen::Playing if pause => self.pause_game(time_secs),
+            Screen::Playing => self.update_playing(time_secs),
+            Screen::Paused { since, resume_at } => self.update_paused(time_secs, since, resume_at),
+            Screen::Finished(_) if self.scene.history_step != 0 => self.step_history(),
+            Screen::Finished(_) => {
+                if self.scene.confirm {
+                    self.scene.confirm = false;
+                    self.select_mode(self.selected_mode);
+                }
             }
         }
+        self.scene.pause = false;
+        self.scene.focus_lost = false;
+        self.scene.history_step = 0;
+    }
 
-        for geo in self.geo_entities.iter_mut() {
-            let mut cpu_primitives = &mut geo.primitives;
-            for idx in 0..num_instances {
-                // let mut cpu_prim = cpu_primitives[(fill_prim_id + idx) as usize];
-                // cpu_prim.width = scene.stroke_width;
-                let col_offset = ((idx % PLAYFIELD_COLS as usize) as f32 * TETRION_SIZE);
-                let row_offset = ((idx / PLAYFIELD_COLS as usize) as f32 * TETRION_SIZE);
-                // cpu_primitives[(fill_prim_id + idx) as usize].translate = [
-                //     3.0 * ((time_secs * 1.5).sin() * 1.0) + col_offset,
-                //     3.0 * ((time_secs * 1.3).sin() * 1.0) + row_offset,
-                // ];
-
-                // Stupid "has fill" check
-                let w = if cpu_primitives[(fill_prim_id + idx) as usize].color[0..3] == [0.0, 0.0, 0.0] {
-
-                    let wr = (((idx / PLAYFIELD_COLS as usize) as f32
-                        - ((time_secs * 0.2).sin().abs() * PLAYFIELD_ROWS as f32)) / PLAYFIELD_ROWS as f32).abs();
-
-                    let wc = (((idx % PLAYFIELD_COLS as usize) as f32
-                        - ((time_secs * 0.5).sin().abs() * PLAYFIELD_COLS as f32)) / PLAYFIELD_COLS as f32).abs();
-
-                    //let wr = 1.0;
+    /// Undoes or redoes a locked piece, which also takes a finished game back into play
+    fn step_history(&mut self) {
+        if !self.modes[self.selected_mode].is_practice() {
+            return;
+        }
+        let name = self.modes[self.selected_mode].name().to_string();
+        let undo = self.scene.history_step < 0;
+        self.scene.history_step = 0;
+        let restored = if undo { self.history.undo(&self.game) } else { self.history.redo(&self.game) };
+        let game = match restored {
+            Some(game) => game,
+            None => {
+                self.set_status(format!("{} - Nothing to {}", name, if undo { "undo" } else { "redo" }));
+                return;
+            }
+        };
+        self.game = game;
+        self.screen = Screen::Playing;
+        self.scene.last_frame_secs = self.anim_secs;
+        self.scene.frame_acc = 0.0;
+        self.update_hint();
+        self.update_field();
+        self.update_tet();
+        self.set_status(name);
+    }
 
-                    /*
-                        ((idx % PLAYFIELD_COLS as usize) as f32 / PLAYFIELD_COLS as f32)
-                        + (time_secs * 0.5).sin().abs();
-                    */
-                    wr * wc
-                } else {
-                    0.0
-                };
+    fn pause_game(&mut self, time_secs: f32) {
+        info!("Paused");
+        self.screen = Screen::Paused { since: time_secs, resume_at: None };
+        self.update_field();
+        self.update_tet();
+        self.set_status("Paused - Esc: resume".to_string());
+    }
 
-                cpu_primitives[idx].color_stroke = [w, w, w, 1.0];// , w, 1.0];
+    fn update_paused(&mut self, time_secs: f32, since: f32, resume_at: Option<f32>) {
+        match resume_at {
+            None if self.scene.pause => {
+                self.screen = Screen::Paused { since, resume_at: Some(time_secs + RESUME_COUNTDOWN) };
+                self.update_field();
+                self.update_tet();
+            }
+            Some(_) if self.scene.focus_lost => {
+                // Back to hiding the board until the player returns
+                self.pause_game(since);
+            }
+            Some(resume_at) if time_secs >= resume_at => {
+                info!("Resumed");
+                // Skip the paused time, so no frames are simulated for it
+                self.scene.last_frame_secs = time_secs;
+                self.scene.target_piece_x = 0;
+                self.scene.rotate = 0;
+                self.scene.hard_drop = false;
+                self.scene.hold = false;
+                self.scene.held_keys.clear();
+                self.scene.shift = 0;
+                self.screen = Screen::Playing;
+                self.set_status(self.modes[self.selected_mode].name().to_string());
+            }
+            Some(resume_at) => {
+                let status = format!("Resuming in {}...", (resume_at - time_secs).ceil());
+                if status != self.status {
+                    self.set_status(status);
+                }
+            }
+            None => {}
+        }
+    }
 
+    /// Flags finesse faults and missed targets in the status
+    fn report_lock(&mut self, result: &LockResult) {
+        let name = self.modes[self.selected_mode].name();
+        let status = match (result.is_finesse_fault(), result.optimal_inputs) {
+            (true, Some(optimal)) => {
+                info!("Finesse fault: {} inputs, {} needed", result.inputs, optimal);
+                format!("{} - Finesse fault: {} keys, {} needed", name, result.inputs, optimal)
+            }
+            _ if result.missed_target => format!("{} - {}", name, self.modes[self.selected_mode].miss_message()),
+            _ if result.garbage_lines > 0 => format!("{} - {} garbage cleared in {}", name,
+                                                     self.game.garbage_cleared,
+                                                     scores::format_time(self.game.time)),
+            _ => name.to_string(),
+        };
+        self.set_status(status);
+    }
 
-                //cpu_primitives[(stroke_prim_id + idx) as usize].color[0] = (row_offset + (time_secs * 0.3)).sin().abs();
-                // cpu_primitives[(stroke_prim_id + idx) as usize].color[1] = (time_secs * 0.01) + (idx as f32).sin().abs();
-                //cpu_primitives[(stroke_prim_id + idx) as usize].color[2] = (col_offset + (time_secs * 0.2)).sin().abs();
+    /// Shows the time of a split and how far ahead (-) or behind (+) the personal best it
+    /// is. The ghost board is tinted green or red to match.
+    fn report_split(&mut self, split: usize) {
+        let time = self.game.line_splits[split];
+        let lines = (split as u32 + 1) * SPLIT_LINES;
+        let delta = self.pb.as_ref().and_then(|pb| pb.splits.get(split)).map(|pb_time| time - pb_time);
+        self.split_delta = delta.or(self.split_delta);
+        let delta = delta.map_or(String::new(), |delta| format!(" ({:+.3})", delta));
+        info!("Split {}: {}{}", lines, scores::format_time(time), delta);
+        self.set_status(format!("{} - {} lines in {}{}", self.modes[self.selected_mode].name(), lines,
+                                scores::format_time(time), delta));
+    }
 
-                if idx == 0{
-                 //println!("COLORS: {:.0}%", cpu_primitives[(stroke_prim_id + idx) as usize].color[0] * 100.0);
+    fn update_select(&mut self) {
+        if self.scene.menu_delta != 0 {
+            let count = self.modes.len() as i32;
+            let index = (self.selected_mode as i32 + self.scene.menu_delta as i32).rem_euclid(count);
+            self.scene.menu_delta = 0;
+            self.select_mode(index as usize);
+        }
+        if self.scene.confirm {
+            self.scene.confirm = false;
+            self.start_game();
+        }
+    }
 
-                }
+    fn update_playing(&mut self, time_secs: f32) {
+        if self.scene.toggle_hint {
+            self.scene.toggle_hint = false;
+            self.scene.show_hint = !self.scene.show_hint;
+            self.update_hint();
+            self.update_title();
+        }
+        if self.scene.history_step != 0 {
+            self.step_history();
+        }
+        self.game.speed = self.scene.speed;
+        self.game.add_inputs(self.scene.key_presses, self.scene.finesse_presses);
+        self.scene.key_presses = 0;
+        self.scene.finesse_presses = 0;
+
+        // Run as many game frames as fit in the time since the last update
+        let frame_secs = 1.0 / self.game.rules.fps;
+        self.scene.frame_acc += (time_secs - self.scene.last_frame_secs).min(MAX_CATCH_UP_SECS);
+        self.scene.last_frame_secs = time_secs;
+        while self.scene.frame_acc >= frame_secs {
+            self.scene.frame_acc -= frame_secs;
+            let input = self.take_frame_input();
+            if let Some(replay) = &mut self.replay {
+                replay.record(&input, self.game.speed);
             }
-
-            let last_down_secs = self.scene.last_down_secs;
-            let speed_mod = (2000.0 / self.scene.speed) / 1000.0;
-
-            if (time_secs - last_down_secs) > speed_mod {
-                self.scene.last_down_secs = time_secs;
-                self.scene.target_piece_y = 1;
+            if let Some(ghost) = &mut self.ghost {
+                ghost.step();
             }
+            if !self.step_game(&input) {
+                return;
+            }
+        }
 
-            // Update player tile
-            if self.scene.target_piece_x != 0 || self.scene.target_piece_y != 0 {
+        // Redrawn every frame to keep the ghost under the piece
+        self.update_field();
+        self.update_tet();
+    }
 
-                let mut new_pos = [
-                    self.scene.curr_tet_pos[0] + self.scene.target_piece_x as i32,
-                    self.scene.curr_tet_pos[1] + self.scene.target_piece_y as i32];
+    /// Starts looking for a perfect clear from the current piece, when hints are on.
+    /// The search runs in the background and is picked up by `poll_hint`.
+    fn update_hint(&mut self) {
+        self.clear_hint();
+        if self.scene.show_hint && self.game.piece.is_some() {
+            let problem = Problem::from_game(&self.game);
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || {
+                // The receiver is gone once the piece has moved on
+                let _ = sender.send(problem.solve(HINT_MAX_PIECES, HINT_MAX_NODES));
+            });
+            self.hint_search = Some(receiver);
+        }
+    }
 
-                // if new_pos[0] < 0 || new_pos[0] > (PLAYFIELD_COLS as i8) - 4 {
-                //     new_pos[0] = self.scene.curr_tet_pos[0];
-                // }
-                //
-                // if new_pos[1] < 0 || new_pos[1] > (PLAYFIELD_ROWS  as i8) - 4 {
-                //     new_pos[1] = self.scene.curr_tet_pos[1];
-                // }
+    /// Takes the result of the perfect clear search, once it is done
+    fn poll_hint(&mut self) {
+        let result = match &self.hint_search {
+            Some(receiver) => receiver.try_recv(),
+            None => return,
+        };
+        match result {
+            Ok(hint) => {
+                debug!("Perfect clear hint: {:?} pieces", hint.as_ref().map(|h| h.len()));
+                self.hint = hint;
+                self.hint_search = None;
+                self.update_title();
+            }
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => self.hint_search = None,
+        }
+    }
 
-                let current_tet_matrix = &self.scene.curr_tet_matrix;
+    /// Drops the hint, and the search for it, when the piece it was for is gone
+    fn clear_hint(&mut self) {
+        self.hint = None;
+        self.hint_search = None;
+    }
 
-                if check_if_free(new_pos, current_tet_matrix, &self.playfield) {
-                    self.scene.curr_tet_pos = new_pos;
+    /// Collects the keys for the next game frame, pressed keys only count for one frame
+    fn take_frame_input(&mut self) -> FrameInput {
+        let scene = &mut self.scene;
+        let input = FrameInput {
+            shift: scene.target_piece_x,
+            held_shift: scene.shift,
+            rotate: scene.rotate,
+            soft_drop: scene.held_keys.contains(&VirtualKeyCode::Down),
+            hard_drop: scene.hard_drop,
+            hold: scene.hold,
+        };
+        scene.target_piece_x = 0;
+        scene.rotate = 0;
+        scene.hard_drop = false;
+        scene.hold = false;
+        input
+    }
 
-                    for r in 0..4 as usize {
-                        for c in 0..4 as usize {
-                            let mut prim = &mut geo.primitives[PLAYFIELD_SIZE as usize + (r * 4) + c];
+    /// Runs a single game frame. Returns false once the game has finished.
+    fn step_game(&mut self, input: &FrameInput) -> bool {
+        let splits = self.game.line_splits.len();
+        let step = self.game.step(input);
+        if step.spawned {
+            self.modes[self.selected_mode].on_spawn(&mut self.game);
+            if self.modes[self.selected_mode].is_practice() {
+                self.history.spawned(&self.game);
+            }
+            self.update_hint();
+            self.update_title();
+        }
 
-                            prim.translate[0] = (new_pos[0] as f32 + c as f32) * TETRION_SIZE;
-                            prim.translate[1] = (new_pos[1] as f32 + r as f32) * TETRION_SIZE;
-                        }
-                    }
+        if let Some(result) = step.lock {
+            debug!("Locked piece: {:?}", result);
+            self.clear_hint();
+            self.history.locked();
+            self.modes[self.selected_mode].on_lock(&mut self.game);
+            self.update_field();
+            self.update_tet();
+            self.report_lock(&result);
+            if self.modes[self.selected_mode].races_pb() {
+                for split in splits..self.game.line_splits.len() {
+                    self.report_split(split);
                 }
-
-                debug!("Player moved {}, {} => {:?}",
-                         self.scene.target_piece_x, self.scene.target_piece_y, new_pos);
-                self.scene.target_piece_x = 0;
-                self.scene.target_piece_y = 0;
             }
+            if let Some(outcome) = self.modes[self.selected_mode].check(&self.game, &result) {
+                self.finish_game(outcome);
+                return false;
+            }
+        }
 
+        if self.game.over {
+            self.finish_game(Outcome::Failure);
+            return false;
         }
+        true
     }
 
     fn render_frame(&mut self, frame: &SwapChainFrame) {
@@ -599,7 +1318,6 @@ impl Engine {
         let scene = &self.scene;
         let queue = &self.queue;
         let multisampled_render_target = &self.multisampled_render_target;
-        let depth_texture_view = &self.depth_texture_view;
         let render_data = self.render_data.as_ref().unwrap();
 
         queue.write_buffer(
@@ -612,13 +1330,17 @@ impl Engine {
                 ],
                 zoom: scene.zoom,
                 scroll_offset: scene.scroll.to_array(),
+                stroke_width: scene.stroke_width,
+                time: self.anim_secs,
                 _pad: 0.0,
             }]),
         );
 
-        for (geo, pbuf) in self.geo_entities.iter()
-            .zip(render_data.prim_buffers.iter()) {
-            queue.write_buffer(&pbuf, 0, bytemuck::cast_slice(&geo.primitives));
+        for geo in self.geo_entities.iter_mut().flatten() {
+            geo.upload_primitives(&self.device, queue, render_data);
+        }
+        if let Some(grid) = &mut self.grid {
+            grid.upload(queue);
         }
 
         // A resolve target is only supported if the attachment actually uses anti-aliasing
@@ -647,41 +1369,15 @@ impl Engine {
             let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: None,
                 color_attachments: &[color_attachment],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: depth_texture_view.as_ref().unwrap(),
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(0.0),
-                        store: true,
-                    }),
-                    stencil_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(0),
-                        store: true,
-                    }),
-                }),
+                depth_stencil_attachment: None,
             });
 
-            for geo in self.geo_entities.iter() {
-                let er = geo.renderer.as_ref().unwrap();
-                pass.set_pipeline(&er.render_pipeline);
-                pass.set_bind_group(0, &render_data.bind_group, &[]);
-                pass.set_index_buffer(er.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-                pass.set_vertex_buffer(0, er.vertex_buffer.slice(..));
-
-                for range in &geo.ranges {
-                //for range in &mut geo.ranges.iter().take(2) {
-                    // TODO: what is going on here? where does 154 come from?
-                    // let start_instance = if range.start != 0 { 154 } else { 0 };
-                    let start_instance = 0;
-                    //pass.draw_indexed(range.clone(), 0, 0..(geo.instances as u32)); // geo.instances as u32
-                    pass.draw_indexed(range.clone(), 0, start_instance..(start_instance + geo.instances as u32));
-                }
-            }
-
+            // Background entities are behind every layer
             for bg in self.bg_entities.iter() {
                 let er = bg.renderer.as_ref().unwrap();
                 let instances = 1;
-                pass.set_pipeline(&er.render_pipeline);
-                pass.set_bind_group(0, &render_data.bind_group, &[]);
+                pass.set_pipeline(&render_data.bg_pipeline);
+                pass.set_bind_group(0, &render_data.globals_bind_group, &[]);
                 pass.set_index_buffer(er.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
                 pass.set_vertex_buffer(0, er.vertex_buffer.slice(..));
 
@@ -689,6 +1385,41 @@ impl Engine {
                     pass.draw_indexed(range.clone(), 0, 0..instances);
                 }
             }
+
+            let mut geos: Vec<&GeoEntity> = self.geo_entities.iter().flatten().collect();
+            // A stable sort, entities of the same order are drawn in the order they were added
+            geos.sort_by_key(|geo| (geo.layer, geo.order));
+            for &layer in Layer::ALL.iter() {
+                if layer == Layer::Field {
+                    if let Some(grid) = &self.grid {
+                        grid.render(&mut pass, &render_data.globals_bind_group);
+                    }
+                }
+                for &geo in geos.iter().filter(|geo| geo.layer == layer) {
+                    let er = geo.renderer.as_ref().unwrap();
+                    let prims = geo.prims.as_ref().unwrap();
+                    if geo.is_instanced() {
+                        pass.set_pipeline(&render_data.geo_instanced_pipeline);
+                        pass.set_bind_group(0, &render_data.globals_bind_group, &[]);
+                        pass.set_vertex_buffer(1, prims.buffer.slice(..));
+                    } else {
+                        pass.set_pipeline(&render_data.geo_pipeline);
+                        pass.set_bind_group(0, &render_data.globals_bind_group, &[]);
+                        pass.set_bind_group(1, &prims.bind_group, &[]);
+                    }
+                    pass.set_index_buffer(er.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+                    pass.set_vertex_buffer(0, er.vertex_buffer.slice(..));
+
+                    for range in &geo.ranges {
+                    //for range in &mut geo.ranges.iter().take(2) {
+                        // TODO: what is going on here? where does 154 come from?
+                        // let start_instance = if range.start != 0 { 154 } else { 0 };
+                        let start_instance = 0;
+                        //pass.draw_indexed(range.clone(), 0, 0..(geo.instances as u32)); // geo.instances as u32
+                        pass.draw_indexed(range.clone(), 0, start_instance..(start_instance + geo.instances as u32));
+                    }
+                }
+            }
         }
 
 
@@ -718,6 +1449,10 @@ impl Engine {
             Event::MainEventsCleared => {
                 return false;
             }
+            Event::LoopDestroyed => {
+                self.save_on_exit();
+                return true;
+            }
             Event::WindowEvent {
                 event: WindowEvent::Destroyed,
                 ..
@@ -745,6 +1480,36 @@ impl Engine {
             } => {
                 scene.cursor_position = (position.x as f32, position.y as f32);
             }
+            Event::WindowEvent {
+                event:
+                WindowEvent::KeyboardInput {
+                    input:
+                    winit::event::KeyboardInput {
+                        state: winit::event::ElementState::Released,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                    ..
+                },
+                ..
+            } => {
+                scene.held_keys.retain(|held| *held != key);
+                match key {
+                    VirtualKeyCode::Left if scene.shift < 0 => scene.shift = 0,
+                    VirtualKeyCode::Right if scene.shift > 0 => scene.shift = 0,
+                    _ => {}
+                }
+            }
+            Event::WindowEvent {
+                event: WindowEvent::Focused(false),
+                ..
+            } => {
+                // Keys released while unfocused never send a Released event, so none of
+                // them can be trusted to still be held
+                scene.focus_lost = true;
+                scene.held_keys.clear();
+                scene.shift = 0;
+            }
             Event::WindowEvent {
                 event: WindowEvent::Resized(size),
                 ..
@@ -764,63 +1529,103 @@ impl Engine {
                     ..
                 },
                 ..
-            } => match key {
-                VirtualKeyCode::Escape => {
-                    *control_flow = ELoop::ControlFlow::Exit;
-                    return false;
-                }
-                VirtualKeyCode::PageDown => {
-                    scene.target_zoom *= 0.8;
-                }
-                VirtualKeyCode::PageUp => {
-                    scene.target_zoom *= 1.25;
-                }
-                VirtualKeyCode::Minus| VirtualKeyCode::O => {
-                    scene.speed *= 0.8;
-                }
-                VirtualKeyCode::Plus | VirtualKeyCode::P => {
-                    scene.speed *= 1.25;
-                }
-                VirtualKeyCode::Left => {
-                    scene.target_piece_x = -1;
-                    //scene.target_scroll.x -= 50.0 / scene.target_zoom;
-                }
-                VirtualKeyCode::Right => {
-                    scene.target_piece_x = 1;
-
-//                    scene.target_scroll.x += 50.0 / scene.target_zoom;
-                }
-                VirtualKeyCode::Up => {
-                    scene.target_piece_y = -1;
-                    //scene.target_scroll.y -= 50.0 / scene.target_zoom;
-                }
-                VirtualKeyCode::Down => {
-                    scene.target_piece_y = 1;
-                    //scene.target_scroll.y += 50.0 / scene.target_zoom;
-                }
-                VirtualKeyCode::Return => {
-                    scene.curr_tet_active = false;
-                }
-                VirtualKeyCode::Space | VirtualKeyCode::X => {
-                    scene.target_tet_rot = (scene.curr_tet_rot + 1) % 4;
-                }
-                VirtualKeyCode::Back | VirtualKeyCode::Z => {
-                    scene.target_tet_rot = ((4 + scene.curr_tet_rot) - 1) % 4;
-                }
-                // VirtualKeyCode::P => {
-                //     scene.show_points = !scene.show_points;
-                // }
-                VirtualKeyCode::B => {
-                    scene.draw_background = !scene.draw_background;
+            } => {
+                let fresh = !scene.held_keys.contains(&key);
+                let das = self.game.rules.das.is_some();
+                if fresh {
+                    scene.held_keys.push(key);
+                    if self.screen == Screen::Playing {
+                        count_press(scene, key);
+                    }
                 }
-                VirtualKeyCode::A => {
-                    scene.target_stroke_width /= 0.8;
+                match key {
+                    VirtualKeyCode::Escape => match self.screen {
+                        Screen::Playing | Screen::Paused { .. } => scene.pause = true,
+                        _ => {
+                            *control_flow = ELoop::ControlFlow::Exit;
+                            return false;
+                        }
+                    }
+                    VirtualKeyCode::Q => {
+                        *control_flow = ELoop::ControlFlow::Exit;
+                        return false;
+                    }
+                    VirtualKeyCode::PageDown => {
+                        scene.target_zoom *= 0.8;
+                    }
+                    VirtualKeyCode::PageUp => {
+                        scene.target_zoom *= 1.25;
+                    }
+                    VirtualKeyCode::Minus| VirtualKeyCode::O => {
+                        scene.speed *= 0.8;
+                    }
+                    VirtualKeyCode::Plus | VirtualKeyCode::P => {
+                        scene.speed *= 1.25;
+                    }
+                    VirtualKeyCode::Left | VirtualKeyCode::Right => {
+                        let dir = if key == VirtualKeyCode::Left { -1 } else { 1 };
+                        // With DAS the shifting is repeated by the game, not by the keyboard
+                        if fresh || !das {
+                            scene.target_piece_x = dir;
+                        }
+                        if fresh {
+                            scene.shift = dir;
+                        }
+                        //scene.target_scroll.x -= 50.0 / scene.target_zoom;
+                    }
+                    VirtualKeyCode::Up => match self.screen {
+                        Screen::Select => scene.menu_delta = -1,
+                        _ => scene.hard_drop = true,
+                        //scene.target_scroll.y -= 50.0 / scene.target_zoom;
+                    }
+                    // Soft drop goes by the held keys
+                    VirtualKeyCode::Down => if self.screen == Screen::Select {
+                        scene.menu_delta = 1;
+                        //scene.target_scroll.y += 50.0 / scene.target_zoom;
+                    }
+                    VirtualKeyCode::Return => {
+                        scene.confirm = true;
+                    }
+                    VirtualKeyCode::Space | VirtualKeyCode::X => {
+                        scene.rotate = 1;
+                    }
+                    VirtualKeyCode::Back | VirtualKeyCode::Z => {
+                        scene.rotate = -1;
+                    }
+                    VirtualKeyCode::C | VirtualKeyCode::LShift => {
+                        scene.hold = true;
+                    }
+                    VirtualKeyCode::F => {
+                        scene.dump_fumen = true;
+                    }
+                    VirtualKeyCode::H => {
+                        scene.toggle_hint = true;
+                    }
+                    VirtualKeyCode::U => {
+                        scene.history_step = -1;
+                    }
+                    VirtualKeyCode::R => {
+                        scene.history_step = 1;
+                    }
+                    VirtualKeyCode::Tab => {
+                        // Picked up by the title on the next FPS report
+                        scene.show_stats = !scene.show_stats;
+                    }
+                    // VirtualKeyCode::P => {
+                    //     scene.show_points = !scene.show_points;
+                    // }
+                    VirtualKeyCode::B => {
+                        scene.draw_background = !scene.draw_background;
+                    }
+                    VirtualKeyCode::A => {
+                        scene.target_stroke_width /= 0.8;
+                    }
+                    // VirtualKeyCode::Z => {
+                    //     scene.target_stroke_width *= 0.8;
+                    // }
+                    _key => {}
                 }
-                // VirtualKeyCode::Z => {
-                //     scene.target_stroke_width *= 0.8;
-                // }
-                _key => {}
-            },
+            }
             _evt => {
                 //println!("{:?}", _evt);
             }
@@ -838,27 +1643,38 @@ impl Engine {
     }
 }
 
-fn check_if_free(pos: [i32 ; 2], tetro_shape: &TetroShape, blocks: &[usize ; PLAYFIELD_SIZE as usize]) -> bool {
-    for r in 0..4 as usize {
-        let offset_row = r as i32 + pos[1];
-        let block_row = offset_row * PLAYFIELD_COLS as i32;
-        for c in 0..4 as usize {
-            let offset_col = c as i32 + pos[0];
-            if match tetro_shape {
-                TetroShape::Odd(t) if c < 3 && r < 3 => t[r][c],
-                TetroShape::Even(t) => t[r][c],
-                _ => false
-            } && (
-                // Check if outside play field
-                offset_row < 0 || offset_row >= PLAYFIELD_ROWS as i32 ||
-                offset_col < 0 || offset_col >= PLAYFIELD_COLS as i32 ||
-                // Check if target block is occupied
-                blocks[block_row as usize + (offset_col as usize)] != 0) { return false; }
-        }
-    }
-    return true;
+/// The board or outline entity, unless it has been removed. Takes the fields rather than the engine
+/// so that the game state can be read alongside.
+fn board_entity<'a>(entities: &'a mut [Option<GeoEntity>], board: &Option<EntityToken>) -> Option<&'a mut GeoEntity> {
+    entities.get_mut(board.as_ref()?.index)?.as_mut()
+}
+
+/// Instance of the board drawing the play field cell at `[col, row]`, cells outside of the
+/// play field get an index past the end
+fn cell_index([col, row]: [i32; 2]) -> usize {
+    if col < 0 || row < 0 {
+        return usize::MAX;
+    }
+    (row * PLAYFIELD_COLS as i32 + col) as usize
 }
 
+/// Counts a fresh key press for the stats. Keys held down are only counted once,
+/// however often the OS repeats them, so that holding a direction is a single input.
+fn count_press(scene: &mut SceneParams, key: VirtualKeyCode) {
+    match key {
+        VirtualKeyCode::Left | VirtualKeyCode::Right |
+        VirtualKeyCode::Space | VirtualKeyCode::X |
+        VirtualKeyCode::Back | VirtualKeyCode::Z => {
+            scene.key_presses += 1;
+            scene.finesse_presses += 1;
+        }
+        VirtualKeyCode::Up | VirtualKeyCode::Down |
+        VirtualKeyCode::C | VirtualKeyCode::LShift => {
+            scene.key_presses += 1;
+        }
+        _ => {}
+    }
+}
 
 /// Creates a texture that uses MSAA and fits a given swap chain
 fn create_multisampled_framebuffer(
@@ -899,13 +1715,27 @@ struct SceneParams {
     window_size: PhysicalSize<u32>,
     size_changed: bool,
     target_piece_x: i8,
-    target_piece_y: i8,
-    target_tet_rot: u8,
-    curr_tet_pos: [i32 ; 2],
-    curr_tet_index: u8,
-    curr_tet_active: bool,
-    curr_tet_rot: u8,
-    curr_tet_matrix: tetrominos::TetroShape,
+    rotate: i8,
+    hard_drop: bool,
+    hold: bool,
+    menu_delta: i8,
+    confirm: bool,
+    dump_fumen: bool,
+    show_stats: bool,
+    show_hint: bool,
+    toggle_hint: bool,
+    /// Undo (-1) or redo (1) asked for, only used in practice modes
+    history_step: i8,
+    /// Keys currently held down, to tell new presses from repeats
+    held_keys: Vec<VirtualKeyCode>,
+    key_presses: u32,
+    finesse_presses: u32,
+    pause: bool,
+    focus_lost: bool,
+    /// Direction held down, for auto shifting
+    shift: i8,
     speed: f32,
-    last_down_secs: f32,
+    last_frame_secs: f32,
+    /// Time not yet simulated, less than a game frame
+    frame_acc: f32,
 }
\ No newline at end of file
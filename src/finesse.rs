@@ -0,0 +1,137 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use crate::game::{Game, Piece, PLAYFIELD_SIZE};
+use crate::rotation::RotationSystem;
+use crate::tetrominos::Tetromino;
+
+/// Cells of a placement with the rows shifted to start at 0, so placements can be compared
+/// regardless of how far the piece was dropped
+pub type Footprint = Vec<[i32; 2]>;
+
+pub fn footprint(cells: &[[i32; 2]]) -> Footprint {
+    let top = cells.iter().map(|[_, r]| *r).min().unwrap_or(0);
+    let mut footprint: Footprint = cells.iter().map(|[c, r]| [*c, r - top]).collect();
+    footprint.sort();
+    footprint
+}
+
+/// Least number of inputs for each placement of a piece
+pub type Placements = HashMap<Footprint, u32>;
+
+lazy_static! {
+    /// Placements only depend on the piece and the rotation system, so each pair is searched once
+    static ref PLACEMENTS: Mutex<HashMap<(Tetromino, &'static str), Arc<Placements>>> = Mutex::new(HashMap::new());
+}
+
+/// Finds the least number of inputs needed for every placement of a piece on an empty board.
+/// Holding a direction until the piece hits the wall counts as a single input.
+pub fn placements(index: Tetromino, rotation: &'static dyn RotationSystem) -> Arc<Placements> {
+    let mut cache = PLACEMENTS.lock().unwrap_or_else(|err| err.into_inner());
+    cache.entry((index, rotation.name()))
+        .or_insert_with(|| Arc::new(search_placements(index, rotation)))
+        .clone()
+}
+
+fn search_placements(index: Tetromino, rotation: &'static dyn RotationSystem) -> Placements {
+    let mut game = Game::with_setup([0; PLAYFIELD_SIZE as usize], Some(&[index]));
+    game.rotation = rotation;
+    let mut placements = HashMap::new();
+    if !game.spawn() {
+        return placements;
+    }
+
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::new();
+    queue.push_back((game.piece.take(), 0));
+    while let Some((piece, inputs)) = queue.pop_front() {
+        let piece = match piece {
+            Some(piece) => piece,
+            None => continue,
+        };
+        if !seen.insert((piece.pos, piece.rot)) {
+            continue;
+        }
+        placements.entry(footprint(&piece.cells())).or_insert(inputs);
+
+        // Only the piece moves, the same empty board is reused for every input
+        for &input in &[Input::Left, Input::Right, Input::WallLeft, Input::WallRight, Input::Cw, Input::Ccw] {
+            game.piece = Some(piece.clone());
+            if input.apply(&mut game) {
+                queue.push_back((game.piece.take(), inputs + 1));
+            }
+        }
+    }
+    placements
+}
+
+/// The optimal number of inputs for the placement of `piece`, if it can be reached
+/// by shifting and rotating at the top of the board
+pub fn optimal_inputs(piece: &Piece, rotation: &'static dyn RotationSystem) -> Option<u32> {
+    placements(piece.index, rotation).get(&footprint(&piece.cells())).copied()
+}
+
+#[derive(Clone, Copy)]
+enum Input {
+    Left,
+    Right,
+    WallLeft,
+    WallRight,
+    Cw,
+    Ccw,
+}
+
+impl Input {
+    fn apply(self, game: &mut Game) -> bool {
+        match self {
+            Input::Left => game.try_move(-1, 0),
+            Input::Right => game.try_move(1, 0),
+            Input::WallLeft => Self::to_wall(game, -1),
+            Input::WallRight => Self::to_wall(game, 1),
+            Input::Cw => game.try_rotate(1),
+            Input::Ccw => game.try_rotate(-1),
+        }
+    }
+
+    fn to_wall(game: &mut Game, dx: i32) -> bool {
+        let mut moved = false;
+        while game.try_move(dx, 0) {
+            moved = true;
+        }
+        moved
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rotation::Rotation;
+    use crate::tetrominos;
+
+    #[test]
+    fn finds_every_placement_of_the_o() {
+        let placements = placements(tetrominos::TO, Rotation::Srs.system());
+        assert_eq!(placements.len(), 9);
+        // Spawns in the middle, one tap or a hold to the wall away from the rest
+        assert_eq!(placements.values().filter(|&&inputs| inputs == 0).count(), 1);
+        assert!(placements.values().all(|&inputs| inputs <= 2));
+    }
+
+    #[test]
+    fn finds_every_placement_of_the_t() {
+        // 8 columns flat up and down, 9 standing on either side
+        assert_eq!(placements(tetrominos::TT, Rotation::Srs.system()).len(), 34);
+    }
+
+    #[test]
+    fn caches_per_piece_and_rotation_system() {
+        let srs = placements(tetrominos::TI, Rotation::Srs.system());
+        assert!(Arc::ptr_eq(&srs, &placements(tetrominos::TI, Rotation::Srs.system())));
+        assert!(!Arc::ptr_eq(&srs, &placements(tetrominos::TI, Rotation::Ars.system())));
+        assert!(!Arc::ptr_eq(&srs, &placements(tetrominos::TL, Rotation::Srs.system())));
+    }
+
+    #[test]
+    fn footprints_ignore_the_height() {
+        assert_eq!(footprint(&[[3, 10], [4, 10], [4, 9]]), footprint(&[[4, 0], [3, 1], [4, 1]]));
+    }
+}
@@ -0,0 +1,636 @@
+//! Import and export of fumen (v115) strings, the format the community uses to share
+//! setups. Fumen fields are 10 wide and 23 tall, and our playfield is mapped onto the
+//! bottom rows of it.
+
+use log::warn;
+use crate::Result;
+use crate::game::{Game, Piece, Playfield, PLAYFIELD_COLS, PLAYFIELD_ROWS, PLAYFIELD_SIZE};
+use crate::rotation::Rotation;
+use crate::tetrominos::{self, Tetromino};
+
+const ENCODE_TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const VERSION_PREFIX: &str = "v115@";
+
+const FIELD_TOP: usize = 23;
+const FIELD_WIDTH: usize = 10;
+// The visible field plus the hidden garbage row below it
+const FIELD_BLOCKS: usize = (FIELD_TOP + 1) * FIELD_WIDTH;
+// Number of fumen rows above our playfield
+const TOP_OFFSET: usize = FIELD_TOP - PLAYFIELD_ROWS as usize;
+
+// Fumen piece ids, indexed by our tetromino index, and the reverse
+const TO_FUMEN: [u8; 9] = [0, 1, 3, 5, 7, 4, 6, 2, FUMEN_GRAY];
+const FROM_FUMEN: [Tetromino; 9] = [0, 1, 7, 2, 5, 3, 6, 4, tetrominos::GARBAGE];
+const FUMEN_GRAY: u8 = 8;
+
+// Comments are packed four characters per five values, from printable ASCII
+const COMMENT_FIRST_CHAR: u8 = b' ';
+const COMMENT_CHARS: u32 = 96;
+
+type Field = [u8; FIELD_BLOCKS];
+
+/// A single page of a fumen
+#[derive(Clone)]
+pub struct Page {
+    pub playfield: Playfield,
+    /// In the default rotation system
+    pub piece: Option<Piece>,
+    /// Whether the piece is placed (and lines cleared) before the next page
+    pub lock: bool,
+    pub comment: String,
+}
+
+impl Page {
+    pub fn from_game(game: &Game) -> Self {
+        Page {
+            playfield: game.playfield,
+            piece: game.piece.clone(),
+            lock: true,
+            comment: String::new(),
+        }
+    }
+}
+
+/// A piece as fumen stores it: type, rotation (0 = reverse, 1 = right, 2 = spawn, 3 = left)
+/// and the position of its center, with y going up from the bottom row
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct FumenPiece {
+    kind: u8,
+    rotation: u8,
+    x: i32,
+    y: i32,
+}
+
+impl FumenPiece {
+    fn blocks(&self) -> Vec<[i32; 2]> {
+        let spawn: [[i32; 2]; 4] = match self.kind {
+            1 => [[0, 0], [-1, 0], [1, 0], [2, 0]],  // I
+            2 => [[0, 0], [-1, 0], [1, 0], [1, 1]],  // L
+            3 => [[0, 0], [1, 0], [0, 1], [1, 1]],   // O
+            4 => [[0, 0], [1, 0], [0, 1], [-1, 1]],  // Z
+            5 => [[0, 0], [-1, 0], [1, 0], [0, 1]],  // T
+            6 => [[0, 0], [-1, 0], [1, 0], [-1, 1]], // J
+            7 => [[0, 0], [-1, 0], [0, 1], [1, 1]],  // S
+            _ => return Vec::new(),
+        };
+        spawn.iter()
+            .map(|&[x, y]| match self.rotation {
+                0 => [-x, -y],
+                1 => [y, -x],
+                3 => [-y, x],
+                _ => [x, y],
+            })
+            .map(|[x, y]| [self.x + x, self.y + y])
+            .collect()
+    }
+
+    // Old fumen versions used a different center for some pieces, which the coordinates
+    // are still stored relative to
+    fn coordinate_fix(&self) -> (i32, i32) {
+        match (self.kind, self.rotation) {
+            (3, 3) => (1, -1),
+            (3, 0) => (1, 0),
+            (3, 2) => (0, -1),
+            (1, 0) => (1, 0),
+            (1, 3) => (0, -1),
+            (7, 2) => (0, -1),
+            (7, 1) => (-1, 0),
+            (4, 2) => (0, -1),
+            (4, 3) => (1, 0),
+            _ => (0, 0),
+        }
+    }
+
+    fn from_coordinate(kind: u8, rotation: u8, coordinate: usize) -> Self {
+        let mut piece = FumenPiece {
+            kind,
+            rotation,
+            x: (coordinate % FIELD_WIDTH) as i32,
+            y: FIELD_TOP as i32 - (coordinate / FIELD_WIDTH) as i32 - 1,
+        };
+        let (dx, dy) = piece.coordinate_fix();
+        piece.x += dx;
+        piece.y += dy;
+        piece
+    }
+
+    fn coordinate(&self) -> usize {
+        if self.kind == 0 {
+            return 0;
+        }
+        let (dx, dy) = self.coordinate_fix();
+        let (x, y) = (self.x - dx, self.y - dy);
+        (FIELD_TOP as i32 - y - 1) as usize * FIELD_WIDTH + x as usize
+    }
+}
+
+/// Reads the base 64 digits of a fumen, least significant first
+struct Values<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Values<'a> {
+    fn poll(&mut self, digits: usize) -> Result<u32> {
+        let mut value = 0;
+        for i in 0..digits {
+            let c = *self.data.get(self.pos).ok_or("unexpected end of fumen data")?;
+            let digit = ENCODE_TABLE.iter().position(|&e| e == c)
+                .ok_or_else(|| format!("invalid fumen character {:?}", c as char))?;
+            value += (digit as u32) * 64u32.pow(i as u32);
+            self.pos += 1;
+        }
+        Ok(value)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+}
+
+fn push_value(out: &mut Vec<u8>, mut value: u32, digits: usize) {
+    for _ in 0..digits {
+        out.push(ENCODE_TABLE[(value % 64) as usize]);
+        value /= 64;
+    }
+}
+
+/// Decodes every page of a fumen. Anything before the version prefix (like the
+/// address of a fumen viewer) is ignored.
+pub fn decode(fumen: &str) -> Result<Vec<Page>> {
+    let start = fumen.find(VERSION_PREFIX)
+        .ok_or("only v115 fumen data is supported")?;
+    let data: Vec<u8> = fumen[start + VERSION_PREFIX.len()..].bytes()
+        .filter(|&c| c != b'?')
+        .collect();
+    let mut values = Values { data: &data, pos: 0 };
+
+    let mut pages = Vec::new();
+    let mut prev: Field = [0; FIELD_BLOCKS];
+    let mut repeat = 0;
+    let mut comment = String::new();
+
+    while !values.is_empty() {
+        let mut field = prev;
+        if repeat > 0 {
+            repeat -= 1;
+        } else {
+            let mut index = 0;
+            while index < FIELD_BLOCKS {
+                let value = values.poll(2)?;
+                let diff = value / FIELD_BLOCKS as u32;
+                let count = (value % FIELD_BLOCKS as u32) as usize + 1;
+                if diff > 16 || index + count > FIELD_BLOCKS {
+                    return Err("invalid fumen field data".into());
+                }
+                for block in field[index..index + count].iter_mut() {
+                    let value = *block as i32 + diff as i32 - 8;
+                    if value < 0 || value > FUMEN_GRAY as i32 {
+                        return Err("invalid fumen field data".into());
+                    }
+                    *block = value as u8;
+                }
+                index += count;
+                if diff == 8 && count == FIELD_BLOCKS {
+                    repeat = values.poll(1)?;
+                }
+            }
+        }
+
+        let mut action = values.poll(3)?;
+        let kind = (action % 8) as u8;
+        action /= 8;
+        let rotation = (action % 4) as u8;
+        action /= 4;
+        let coordinate = (action % FIELD_BLOCKS as u32) as usize;
+        action /= FIELD_BLOCKS as u32;
+        let rise = action % 2 == 1;
+        action /= 2;
+        let mirror = action % 2 == 1;
+        action /= 2;
+        let _colorize = action % 2 == 1;
+        action /= 2;
+        let has_comment = action % 2 == 1;
+        action /= 2;
+        let lock = action % 2 == 0;
+
+        if has_comment {
+            comment = decode_comment(&mut values)?;
+        }
+
+        let piece = FumenPiece::from_coordinate(kind, rotation, coordinate);
+        pages.push(Page {
+            playfield: to_playfield(&field)?,
+            piece: if kind == 0 { None } else { Some(to_piece(&piece)?) },
+            lock,
+            comment: comment.clone(),
+        });
+
+        if lock {
+            for [x, y] in piece.blocks() {
+                if x >= 0 && x < FIELD_WIDTH as i32 && y >= 0 && y < FIELD_TOP as i32 {
+                    field[(FIELD_TOP - 1 - y as usize) * FIELD_WIDTH + x as usize] = kind;
+                }
+            }
+            clear_lines(&mut field);
+            if rise {
+                field.copy_within(FIELD_WIDTH.., 0);
+                for block in field[FIELD_BLOCKS - FIELD_WIDTH..].iter_mut() {
+                    *block = 0;
+                }
+            }
+            if mirror {
+                for row in field[..FIELD_TOP * FIELD_WIDTH].chunks_mut(FIELD_WIDTH) {
+                    row.reverse();
+                }
+            }
+        }
+        prev = field;
+    }
+
+    if pages.is_empty() {
+        return Err("fumen does not contain any pages".into());
+    }
+    Ok(pages)
+}
+
+/// Encodes pages into a fumen string
+pub fn encode(pages: &[Page]) -> String {
+    // Work out the field each page is diffed against up front, since a field that didn't
+    // change also records how many of the following pages didn't change either
+    let mut fields = Vec::with_capacity(pages.len());
+    let mut prevs = Vec::with_capacity(pages.len());
+    let mut prev: Field = [0; FIELD_BLOCKS];
+    for page in pages {
+        let mut field = to_field(&page.playfield);
+        fields.push(field);
+        prevs.push(prev);
+        if page.lock {
+            if let Some(piece) = page.piece.as_ref().and_then(from_piece) {
+                for [x, y] in piece.blocks() {
+                    field[(FIELD_TOP - 1 - y as usize) * FIELD_WIDTH + x as usize] = piece.kind;
+                }
+            }
+            clear_lines(&mut field);
+        }
+        prev = field;
+    }
+    let unchanged: Vec<bool> = fields.iter().zip(prevs.iter()).map(|(f, p)| f == p).collect();
+
+    let mut out = Vec::new();
+    let mut skip = 0;
+    let mut prev_comment = "";
+    for (i, page) in pages.iter().enumerate() {
+        if skip > 0 {
+            skip -= 1;
+        } else {
+            let diffs: Vec<u32> = fields[i].iter().zip(prevs[i].iter())
+                .map(|(&f, &p)| f as u32 + 8 - p as u32)
+                .collect();
+            let mut index = 0;
+            while index < FIELD_BLOCKS {
+                let count = diffs[index..].iter().take_while(|&&d| d == diffs[index]).count();
+                push_value(&mut out, diffs[index] * FIELD_BLOCKS as u32 + count as u32 - 1, 2);
+                index += count;
+            }
+            if unchanged[i] {
+                skip = unchanged[i + 1..].iter().take_while(|&&u| u).take(63).count();
+                push_value(&mut out, skip as u32, 1);
+            }
+        }
+
+        let piece = page.piece.as_ref().and_then(from_piece)
+            .unwrap_or(FumenPiece { kind: 0, rotation: 0, x: 0, y: 0 });
+        let has_comment = page.comment != prev_comment;
+        let mut action = if page.lock { 0 } else { 1 };
+        action = action * 2 + has_comment as u32;
+        action = action * 2 + (i == 0) as u32; // colorize
+        action *= 2; // mirror
+        action *= 2; // rise
+        action = action * FIELD_BLOCKS as u32 + piece.coordinate() as u32;
+        action = action * 4 + piece.rotation as u32;
+        action = action * 8 + piece.kind as u32;
+        push_value(&mut out, action, 3);
+
+        if has_comment {
+            encode_comment(&mut out, &page.comment);
+            prev_comment = &page.comment;
+        }
+    }
+
+    // Like other encoders, break the data with a '?' after the first 42 characters and
+    // then after every 47
+    let data = String::from_utf8(out).unwrap();
+    let mut fumen = String::from(VERSION_PREFIX);
+    fumen.push_str(&data[..data.len().min(42)]);
+    if data.len() > 42 {
+        for chunk in data.as_bytes()[42..].chunks(47) {
+            fumen.push('?');
+            fumen.push_str(std::str::from_utf8(chunk).unwrap());
+        }
+    }
+    fumen
+}
+
+/// A short name for the diagram of `pages`, the same however the fumen was written down
+pub fn fingerprint(pages: &[Page]) -> String {
+    // FNV-1a, which unlike the std hasher is stable across releases
+    let hash = encode(pages).bytes()
+        .fold(0x811c_9dc5u32, |hash, b| (hash ^ b as u32).wrapping_mul(0x0100_0193));
+    format!("{:08x}", hash)
+}
+
+fn decode_comment(values: &mut Values) -> Result<String> {
+    let length = values.poll(2)? as usize;
+    let mut escaped = String::with_capacity(length);
+    for _ in 0..length.div_ceil(4) {
+        let mut value = values.poll(5)?;
+        for _ in 0..4 {
+            escaped.push((COMMENT_FIRST_CHAR + (value % COMMENT_CHARS) as u8) as char);
+            value /= COMMENT_CHARS;
+        }
+    }
+    escaped.truncate(length);
+    Ok(unescape(&escaped))
+}
+
+fn encode_comment(out: &mut Vec<u8>, comment: &str) {
+    let escaped = escape(comment);
+    push_value(out, escaped.len() as u32, 2);
+    for chunk in escaped.as_bytes().chunks(4) {
+        let mut value = 0;
+        for (i, c) in chunk.iter().enumerate() {
+            value += (*c - COMMENT_FIRST_CHAR) as u32 * COMMENT_CHARS.pow(i as u32);
+        }
+        push_value(out, value, 5);
+    }
+}
+
+// Comments are stored using javascript's escape(), which leaves these characters as-is
+fn is_unescaped(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "@*_+-./".contains(c)
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        if is_unescaped(c) {
+            escaped.push(c);
+        } else if (c as u32) < 0x100 {
+            escaped.push_str(&format!("%{:02X}", c as u32));
+        } else {
+            let mut units = [0; 2];
+            for unit in c.encode_utf16(&mut units).iter() {
+                escaped.push_str(&format!("%u{:04X}", unit));
+            }
+        }
+    }
+    escaped
+}
+
+fn unescape(s: &str) -> String {
+    let mut units = Vec::new();
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        let code = if rest.starts_with("%u") {
+            rest.get(2..6).and_then(|h| u16::from_str_radix(h, 16).ok()).map(|n| (n, 6))
+        } else if c == '%' {
+            rest.get(1..3).and_then(|h| u16::from_str_radix(h, 16).ok()).map(|n| (n, 3))
+        } else {
+            None
+        };
+        match code {
+            Some((unit, len)) => {
+                units.push(unit);
+                rest = &rest[len..];
+            }
+            None => {
+                let mut buf = [0; 2];
+                units.extend_from_slice(c.encode_utf16(&mut buf));
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    String::from_utf16_lossy(&units)
+}
+
+fn clear_lines(field: &mut Field) {
+    let mut dst = FIELD_TOP;
+    for src in (0..FIELD_TOP).rev() {
+        let row = src * FIELD_WIDTH..(src + 1) * FIELD_WIDTH;
+        if field[row.clone()].iter().all(|&b| b != 0) {
+            continue;
+        }
+        dst -= 1;
+        if dst != src {
+            field.copy_within(row, dst * FIELD_WIDTH);
+        }
+    }
+    for block in field[..dst * FIELD_WIDTH].iter_mut() {
+        *block = 0;
+    }
+}
+
+fn to_field(playfield: &Playfield) -> Field {
+    let mut field = [0; FIELD_BLOCKS];
+    for (i, &block) in playfield.iter().enumerate() {
+        field[TOP_OFFSET * FIELD_WIDTH + i] = TO_FUMEN.get(block).copied().unwrap_or(FUMEN_GRAY);
+    }
+    field
+}
+
+fn to_playfield(field: &Field) -> Result<Playfield> {
+    if field[..TOP_OFFSET * FIELD_WIDTH].iter().any(|&b| b != 0) {
+        return Err(format!("fumen field is taller than {} rows", PLAYFIELD_ROWS).into());
+    }
+    if field[FIELD_TOP * FIELD_WIDTH..].iter().any(|&b| b != 0) {
+        warn!("Ignoring the garbage row of the fumen field");
+    }
+    let mut playfield = [0; PLAYFIELD_SIZE as usize];
+    for (i, block) in playfield.iter_mut().enumerate() {
+        *block = FROM_FUMEN[field[TOP_OFFSET * FIELD_WIDTH + i] as usize];
+    }
+    Ok(playfield)
+}
+
+fn fumen_to_cell([x, y]: [i32; 2]) -> [i32; 2] {
+    [x, PLAYFIELD_ROWS as i32 - 1 - y]
+}
+
+fn sorted(mut cells: Vec<[i32; 2]>) -> Vec<[i32; 2]> {
+    cells.sort_by_key(|&[c, r]| (r, c));
+    cells
+}
+
+// Returns the offset that moves `cells` onto `target`, if they have the same shape
+fn shape_offset(cells: &[[i32; 2]], target: &[[i32; 2]]) -> Option<[i32; 2]> {
+    let offset = [target[0][0] - cells[0][0], target[0][1] - cells[0][1]];
+    let matches = cells.len() == target.len() && cells.iter().zip(target.iter())
+        .all(|(a, b)| a[0] + offset[0] == b[0] && a[1] + offset[1] == b[1]);
+    if matches { Some(offset) } else { None }
+}
+
+// Our pieces rotate within a box rather than around a center, so the piece is matched
+// by finding the rotation that covers the same cells
+fn to_piece(fumen_piece: &FumenPiece) -> Result<Piece> {
+    let rotation = Rotation::default().system();
+    let index = FROM_FUMEN[fumen_piece.kind as usize];
+    let target = sorted(fumen_piece.blocks().into_iter().map(fumen_to_cell).collect());
+    for rot in 0..4 {
+        let mut piece = Piece::new(index, rotation);
+        piece.pos = [0, 0];
+        piece.rot = rot;
+        piece.matrix = rotation.shape(index, rot);
+        if let Some(offset) = shape_offset(&sorted(piece.cells()), &target) {
+            piece.pos = offset;
+            if piece.cells().iter().all(|&[c, r]| r >= 0 && c >= 0 && c < PLAYFIELD_COLS as i32) {
+                return Ok(piece);
+            }
+        }
+    }
+    Err(format!("fumen piece {} does not fit in the playfield", tetrominos::NAMES[index]).into())
+}
+
+fn from_piece(piece: &Piece) -> Option<FumenPiece> {
+    let target = sorted(piece.cells());
+    (0..4).find_map(|rotation| {
+        let kind = TO_FUMEN[piece.index];
+        let origin = FumenPiece { kind, rotation, x: 0, y: 0 };
+        shape_offset(&sorted(origin.blocks().into_iter().map(fumen_to_cell).collect()), &target)
+            .map(|[dx, dy]| FumenPiece { x: dx, y: -dy, ..origin })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The empty field, as every fumen editor starts out
+    const EMPTY: &str = "v115@vhAAgH";
+    // A T in spawn orientation, centered on column 4 of the bottom row
+    const T_SPAWN: &str = "v115@vhAVQJ";
+
+    fn cells(page: &Page) -> Vec<[i32; 2]> {
+        sorted(page.piece.as_ref().unwrap().cells())
+    }
+
+    #[test]
+    fn decodes_empty_field() {
+        let pages = decode(EMPTY).unwrap();
+        assert_eq!(pages.len(), 1);
+        assert!(pages[0].playfield.iter().all(|&b| b == 0));
+        assert!(pages[0].piece.is_none());
+        assert!(pages[0].lock);
+        assert_eq!(pages[0].comment, "");
+        assert_eq!(encode(&pages), EMPTY);
+    }
+
+    #[test]
+    fn decodes_piece() {
+        let pages = decode(T_SPAWN).unwrap();
+        let piece = pages[0].piece.as_ref().unwrap();
+        assert_eq!(piece.index, tetrominos::TT);
+        let bottom = PLAYFIELD_ROWS as i32 - 1;
+        assert_eq!(cells(&pages[0]), vec![[4, bottom - 1], [3, bottom], [4, bottom], [5, bottom]]);
+        assert_eq!(encode(&pages), T_SPAWN);
+    }
+
+    #[test]
+    fn ignores_viewer_address_and_line_breaks() {
+        let pages = decode(&format!("https://fumen.zui.jp/?{}", T_SPAWN)).unwrap();
+        assert_eq!(encode(&pages), T_SPAWN);
+        let split = "v115@vh?AVQJ";
+        assert_eq!(encode(&decode(split).unwrap()), T_SPAWN);
+    }
+
+    #[test]
+    fn round_trips_pages() {
+        let mut playfield = [0; PLAYFIELD_SIZE as usize];
+        let bottom = (PLAYFIELD_ROWS - 1) * PLAYFIELD_COLS;
+        for (col, block) in playfield[bottom as usize..].iter_mut().enumerate() {
+            *block = if col == 9 { 0 } else { tetrominos::GARBAGE };
+        }
+        let mut first = Page {
+            playfield,
+            piece: None,
+            lock: true,
+            comment: "Clear it, 100% (ü)".to_string(),
+        };
+        let mut piece = Piece::new(tetrominos::TI, Rotation::default().system());
+        piece.rot = 1;
+        piece.matrix = Rotation::default().system().shape(tetrominos::TI, 1);
+        piece.pos = [9 - piece.cells()[0][0], 0];
+        let bottom_row = PLAYFIELD_ROWS as i32 - 1;
+        let top = piece.cells().iter().map(|c| c[1]).max().unwrap();
+        piece.pos[1] += bottom_row - top;
+        first.piece = Some(piece);
+
+        // Enough pages for the data to be broken up with '?'
+        let mut pages = vec![first];
+        for i in 0..12 {
+            let mut page = Page::from_game(&Game::new());
+            page.playfield = [0; PLAYFIELD_SIZE as usize];
+            page.playfield[i] = tetrominos::TL;
+            pages.push(page);
+        }
+
+        let fumen = encode(&pages);
+        assert!(fumen.contains('?'));
+        let decoded = decode(&fumen).unwrap();
+        assert_eq!(decoded.len(), pages.len());
+        for (page, decoded) in pages.iter().zip(decoded.iter()) {
+            assert_eq!(&page.playfield[..], &decoded.playfield[..]);
+            assert_eq!(page.piece.is_some(), decoded.piece.is_some());
+            if page.piece.is_some() {
+                assert_eq!(cells(page), cells(decoded));
+            }
+        }
+        assert_eq!(decoded[0].comment, "Clear it, 100% (ü)");
+        assert_eq!(decoded[1].comment, "");
+        assert_eq!(encode(&decoded), fumen);
+    }
+
+    #[test]
+    fn locking_clears_lines() {
+        let mut playfield = [0; PLAYFIELD_SIZE as usize];
+        let bottom = ((PLAYFIELD_ROWS - 1) * PLAYFIELD_COLS) as usize;
+        for block in playfield[bottom..bottom + 6].iter_mut() {
+            *block = tetrominos::GARBAGE;
+        }
+        // An I lying flat in the rest of the bottom row
+        let mut piece = Piece::new(tetrominos::TI, Rotation::default().system());
+        let [col, row] = sorted(piece.cells())[0];
+        piece.pos = [piece.pos[0] + 6 - col, piece.pos[1] + PLAYFIELD_ROWS as i32 - 1 - row];
+        let empty = [0; PLAYFIELD_SIZE as usize];
+        let pages = vec![
+            Page { playfield, piece: Some(piece), lock: true, comment: String::new() },
+            Page { playfield: empty, piece: None, lock: true, comment: String::new() },
+        ];
+        // The second page is stored as an unchanged field, followed by an empty action
+        let fumen = encode(&pages);
+        assert_eq!(fumen, format!("{}vhAAAA", encode(&pages[..1])));
+        assert!(decode(&fumen).unwrap()[1].playfield.iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn rejects_bad_data() {
+        assert!(decode("v110@vhAAgH").is_err());
+        assert!(decode("v115@").is_err());
+        assert!(decode("v115@vhA").is_err());
+        assert!(decode("v115@vh!AgH").is_err());
+    }
+
+    #[test]
+    fn fingerprint_follows_the_diagram() {
+        let empty = decode(EMPTY).unwrap();
+        let split = decode("https://fumen.zui.jp/?v115@vh?AAgH").unwrap();
+        assert_eq!(fingerprint(&empty), fingerprint(&split));
+        assert_ne!(fingerprint(&empty), fingerprint(&decode(T_SPAWN).unwrap()));
+    }
+
+    #[test]
+    fn escapes_like_javascript() {
+        assert_eq!(escape("a b%ü€"), "a%20b%25%FC%u20AC");
+        assert_eq!(unescape("a%20b%25%FC%u20AC"), "a b%ü€");
+    }
+}
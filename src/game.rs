@@ -0,0 +1,980 @@
+use std::collections::VecDeque;
+use rand::{Rng, SeedableRng};
+use rand::seq::SliceRandom;
+use rand_chacha::ChaCha12Rng;
+use serde::{Serialize, Deserialize};
+use log::info;
+use crate::tetrominos::{self, Tetromino, TetroShape};
+use crate::stats::Stats;
+use crate::finesse;
+use crate::rotation::RotationSystem;
+use crate::rules::{GravityRate, Randomizer, Rules, Scoring};
+use crate::grading::Grading;
+use crate::save;
+
+pub const PLAYFIELD_COLS: u32 = 10;
+pub const PLAYFIELD_ROWS: u32 = 16;
+pub const PLAYFIELD_SIZE: u32 = PLAYFIELD_COLS * PLAYFIELD_ROWS;
+
+// Number of upcoming pieces to keep when the queue is randomly generated
+const QUEUE_LEN: usize = 5;
+/// Lines between the times kept in `Game::line_splits`
+pub const SPLIT_LINES: u32 = 10;
+
+pub type Playfield = [usize; PLAYFIELD_SIZE as usize];
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Piece {
+    pub index: Tetromino,
+    pub pos: [i32; 2],
+    pub rot: u8,
+    /// Not saved, it follows from the rotation system
+    #[serde(skip)]
+    pub matrix: TetroShape,
+}
+
+impl Piece {
+    pub fn new(index: Tetromino, rotation: &dyn RotationSystem) -> Self {
+        let rot = rotation.spawn_orientation(index);
+        Piece {
+            index,
+            pos: rotation.spawn_position(index),
+            rot,
+            matrix: rotation.shape(index, rot),
+        }
+    }
+
+    /// Returns the playfield position (column, row) of every solid block
+    pub fn cells(&self) -> Vec<[i32; 2]> {
+        let mut cells = Vec::with_capacity(4);
+        for r in 0..4 {
+            for c in 0..4 {
+                if self.matrix.is_solid(c, r) {
+                    cells.push([self.pos[0] + c as i32, self.pos[1] + r as i32]);
+                }
+            }
+        }
+        cells
+    }
+}
+
+/// What happened when a piece was locked into the playfield
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LockResult {
+    pub lines: u32,
+    pub t_spin: bool,
+    pub perfect_clear: bool,
+    /// Movement and rotation keys pressed for the piece
+    pub inputs: u32,
+    /// Least number of inputs needed, unknown when the piece was soft dropped into place
+    pub optimal_inputs: Option<u32>,
+    /// The piece didn't end up on the target placement
+    pub missed_target: bool,
+    /// Cleared lines that contained garbage
+    pub garbage_lines: u32,
+    /// Rows the piece was soft dropped
+    pub soft_rows: u32,
+}
+
+/// The keys for one frame of `Game::step`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameInput {
+    /// Shift pressed this frame, -1 for left and 1 for right
+    pub shift: i8,
+    /// Direction held down, which auto shifts once DAS has charged
+    pub held_shift: i8,
+    pub rotate: i8,
+    /// Soft drop is held down
+    pub soft_drop: bool,
+    pub hard_drop: bool,
+    pub hold: bool,
+}
+
+/// What happened during one frame of `Game::step`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Step {
+    pub spawned: bool,
+    pub lock: Option<LockResult>,
+}
+
+impl LockResult {
+    /// Points awarded for the lock, using the guideline values for level 1
+    pub fn score(&self) -> u32 {
+        let clear = match (self.lines, self.t_spin) {
+            (0, true) => 400,
+            (1, true) => 800,
+            (2, true) => 1200,
+            (3, true) => 1600,
+            (1, false) => 100,
+            (2, false) => 300,
+            (3, false) => 500,
+            (4, _) => 800,
+            _ => 0,
+        };
+        let perfect_clear = match (self.lines, self.perfect_clear) {
+            (1, true) => 800,
+            (2, true) => 1200,
+            (3, true) => 1800,
+            (4, true) => 2000,
+            _ => 0,
+        };
+        clear + perfect_clear
+    }
+
+    pub fn is_finesse_fault(&self) -> bool {
+        self.optimal_inputs.is_some_and(|optimal| self.inputs > optimal)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Game {
+    #[serde(with = "save::playfield")]
+    pub playfield: Playfield,
+    pub piece: Option<Piece>,
+    pub hold: Option<Tetromino>,
+    pub queue: VecDeque<Tetromino>,
+    pub lines: u32,
+    pub score: u32,
+    /// Seconds played, advanced by whoever runs the game
+    pub time: f32,
+    pub over: bool,
+    pub seed: u32,
+    pub stats: Stats,
+    pub rules: Rules,
+    /// Not saved, see `restore`
+    #[serde(skip, default = "default_rotation")]
+    pub rotation: &'static dyn RotationSystem,
+    pub level: u32,
+    /// Gravity multiplier for rules that let the player choose the speed
+    pub speed: f32,
+    pub grading: Grading,
+    /// Cells the active piece should be placed on, used by the finesse trainer
+    pub target: Option<Vec<[i32; 2]>>,
+    /// Garbage rows added and cleared so far, along with the time of every clear
+    pub garbage_added: u32,
+    pub garbage_cleared: u32,
+    pub garbage_splits: Vec<f32>,
+    /// Time at which every `SPLIT_LINES` lines were reached
+    #[serde(default)]
+    pub line_splits: Vec<f32>,
+    fixed_sequence: bool,
+    hold_used: bool,
+    last_rotated: bool,
+    inputs: u32,
+    soft_dropped: bool,
+    soft_rows: u32,
+    garbage_hole: Option<i32>,
+    // The generator behind `StdRng`, which unlike it can be saved
+    #[serde(with = "save::rng")]
+    rng: ChaCha12Rng,
+    // Pieces left in the current bag of the bag randomizer
+    bag: Vec<Tetromino>,
+    // Frame counters of `step`
+    are: u32,
+    das_dir: i8,
+    das_frames: u32,
+    gravity_frames: u32,
+    lock_frames: u32,
+    lock_resets: u32,
+    lowest_row: i32,
+    combo: u32,
+}
+
+fn default_rotation() -> &'static dyn RotationSystem {
+    Rules::default().rotation.system()
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Game {
+    pub fn new() -> Self {
+        Self::with_setup([0; PLAYFIELD_SIZE as usize], None)
+    }
+
+    /// Creates a game starting from `playfield`. When a `sequence` is given, only those
+    /// pieces are dealt, otherwise the queue is filled with random pieces forever.
+    pub fn with_setup(playfield: Playfield, sequence: Option<&[Tetromino]>) -> Self {
+        Self::with_seed(playfield, sequence, rand::random())
+    }
+
+    /// Like `with_setup`, but with a known seed for the random pieces and garbage
+    pub fn with_seed(playfield: Playfield, sequence: Option<&[Tetromino]>, seed: u32) -> Self {
+        let mut game = Game {
+            playfield,
+            piece: None,
+            hold: None,
+            queue: VecDeque::new(),
+            lines: 0,
+            score: 0,
+            time: 0.0,
+            over: false,
+            seed,
+            stats: Stats::default(),
+            rules: Rules::default(),
+            rotation: Rules::default().rotation.system(),
+            level: Rules::default().start_level,
+            speed: 1.0,
+            grading: Grading::default(),
+            target: None,
+            garbage_added: 0,
+            garbage_cleared: 0,
+            garbage_splits: Vec::new(),
+            line_splits: Vec::new(),
+            fixed_sequence: sequence.is_some(),
+            hold_used: false,
+            last_rotated: false,
+            inputs: 0,
+            soft_dropped: false,
+            soft_rows: 0,
+            garbage_hole: None,
+            rng: ChaCha12Rng::seed_from_u64(seed as u64),
+            bag: Vec::new(),
+            are: 0,
+            das_dir: 0,
+            das_frames: 0,
+            gravity_frames: 0,
+            lock_frames: 0,
+            lock_resets: 0,
+            lowest_row: 0,
+            combo: 1,
+        };
+        match sequence {
+            Some(sequence) => game.queue.extend(sequence.iter()),
+            None => game.fill_queue(),
+        }
+        game
+    }
+
+    /// Sets up what isn't saved with the game again, after it has been loaded
+    pub fn restore(&mut self) {
+        self.rotation = self.rules.rotation.system();
+        if let Some(piece) = &mut self.piece {
+            piece.matrix = self.rotation.shape(piece.index, piece.rot);
+        }
+    }
+
+    /// Switches to another ruleset, which should happen before the first piece is spawned
+    pub fn set_rules(&mut self, rules: Rules) {
+        self.rotation = rules.rotation.system();
+        self.level = rules.start_level;
+        self.rules = rules;
+        self.rules.update_level(self.level);
+        if !self.fixed_sequence {
+            self.queue.clear();
+            self.bag.clear();
+            self.fill_queue();
+        }
+    }
+
+    fn fill_queue(&mut self) {
+        if self.fixed_sequence {
+            return;
+        }
+        while self.queue.len() < QUEUE_LEN {
+            let index = match self.rules.randomizer {
+                Randomizer::Uniform => self.rng.gen_range(tetrominos::TI..=tetrominos::TL),
+                Randomizer::NesReroll => {
+                    let last = self.queue.back().copied()
+                        .or_else(|| self.piece.as_ref().map(|p| p.index));
+                    // One past the last piece is the reroll slot
+                    let index = self.rng.gen_range(tetrominos::TI..=tetrominos::TL + 1);
+                    if index > tetrominos::TL || Some(index) == last {
+                        self.rng.gen_range(tetrominos::TI..=tetrominos::TL)
+                    } else {
+                        index
+                    }
+                }
+                Randomizer::Bag => {
+                    if self.bag.is_empty() {
+                        self.bag.extend(tetrominos::TI..=tetrominos::TL);
+                        self.bag.shuffle(&mut self.rng);
+                    }
+                    self.bag.pop().unwrap_or(tetrominos::TI)
+                }
+            };
+            self.queue.push_back(index);
+        }
+    }
+
+    /// The upcoming pieces the rules allow the player to see
+    pub fn previews(&self) -> impl Iterator<Item = &Tetromino> {
+        self.queue.iter().take(self.rules.previews)
+    }
+
+    /// Spawns the next piece from the queue, or the held piece once the queue has run out.
+    /// Returns false, and ends the game, if there is no piece left or it cannot be placed.
+    pub fn spawn(&mut self) -> bool {
+        let next = self.queue.pop_front().or_else(|| self.hold.take());
+        self.fill_queue();
+        match next {
+            Some(index) => self.spawn_piece(index),
+            None => {
+                info!("Out of pieces!");
+                self.over = true;
+                false
+            }
+        }
+    }
+
+    fn spawn_piece(&mut self, index: Tetromino) -> bool {
+        self.place_piece(Piece::new(index, self.rotation))
+    }
+
+    /// Puts `piece` in play as if it had just spawned there, like the piece of a fumen page
+    pub fn place_piece(&mut self, piece: Piece) -> bool {
+        self.last_rotated = false;
+        self.inputs = 0;
+        self.soft_dropped = false;
+        self.soft_rows = 0;
+        self.gravity_frames = 0;
+        self.lock_frames = 0;
+        self.lock_resets = 0;
+        self.lowest_row = piece.pos[1];
+        if check_if_free(piece.pos, &piece.matrix, &self.playfield) {
+            info!("New tetromino: {:?}", tetrominos::NAMES[piece.index]);
+            self.piece = Some(piece);
+            true
+        } else {
+            info!("Topped out!");
+            self.piece = None;
+            self.over = true;
+            false
+        }
+    }
+
+    pub fn try_move(&mut self, dx: i32, dy: i32) -> bool {
+        if let Some(piece) = &mut self.piece {
+            let new_pos = [piece.pos[0] + dx, piece.pos[1] + dy];
+            if check_if_free(new_pos, &piece.matrix, &self.playfield) {
+                piece.pos = new_pos;
+                self.last_rotated = false;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Rotates the active piece by `steps` quarter turns, clockwise for positive values,
+    /// kicking it to the first free spot offered by the rotation system
+    pub fn try_rotate(&mut self, steps: i8) -> bool {
+        if let Some(piece) = &mut self.piece {
+            let rot = (piece.rot as i8 + steps).rem_euclid(4) as u8;
+            let matrix = self.rotation.shape(piece.index, rot);
+            for [dx, dy] in self.rotation.kicks(piece, rot, &self.playfield) {
+                let pos = [piece.pos[0] + dx, piece.pos[1] + dy];
+                if check_if_free(pos, &matrix, &self.playfield) {
+                    piece.pos = pos;
+                    piece.rot = rot;
+                    piece.matrix = matrix;
+                    self.last_rotated = true;
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Whether hold was already used for the active piece
+    pub fn hold_used(&self) -> bool {
+        self.hold_used
+    }
+
+    /// Swaps the active piece with the held one. Only allowed once per locked piece.
+    pub fn hold(&mut self) -> bool {
+        if self.hold_used || !self.rules.hold {
+            return false;
+        }
+        let piece = match self.piece.take() {
+            Some(piece) => piece,
+            None => return false,
+        };
+        self.hold_used = true;
+        match self.hold.replace(piece.index) {
+            Some(index) => self.spawn_piece(index),
+            None => self.spawn(),
+        }
+    }
+
+    pub fn soft_drop(&mut self) -> bool {
+        let moved = self.try_move(0, 1);
+        if moved {
+            // TGM scoring counts the rows into the line clear bonus instead
+            if self.rules.scoring != Scoring::Tgm {
+                self.score += 1;
+            }
+            self.soft_dropped = true;
+            self.soft_rows += 1;
+        }
+        moved
+    }
+
+    pub fn hard_drop(&mut self) -> Option<LockResult> {
+        if !self.rules.hard_drop {
+            return None;
+        }
+        while self.try_move(0, 1) {
+            self.score += 2;
+        }
+        self.lock()
+    }
+
+    /// Where the active piece would land if dropped
+    pub fn ghost(&self) -> Option<Piece> {
+        let mut ghost = self.piece.clone()?;
+        while check_if_free([ghost.pos[0], ghost.pos[1] + 1], &ghost.matrix, &self.playfield) {
+            ghost.pos[1] += 1;
+        }
+        Some(ghost)
+    }
+
+    /// Advances the game by one frame of `rules.fps`: waits out the entry delay, spawns,
+    /// applies the keys, auto shift and gravity, and locks the piece once its lock delay
+    /// has run out
+    pub fn step(&mut self, input: &FrameInput) -> Step {
+        let mut step = Step::default();
+        self.time += 1.0 / self.rules.fps;
+        self.charge_das(input.held_shift);
+
+        if self.piece.is_none() {
+            if self.are > 0 {
+                self.are -= 1;
+                return step;
+            }
+            if self.stats.pieces > 0 {
+                self.set_level(self.rules.next_level(self.level, 0, self.lines));
+            }
+            if !self.spawn() {
+                return step;
+            }
+            step.spawned = true;
+        }
+
+        if input.hold && self.hold() {
+            step.spawned = true;
+        }
+        if self.piece.is_none() {
+            return step;
+        }
+        if input.rotate != 0 && self.try_rotate(input.rotate) {
+            self.reset_lock_delay();
+        }
+        let mut shift = input.shift;
+        if let Some((delay, repeat)) = self.rules.das {
+            if self.das_dir != 0 && self.das_frames >= delay {
+                shift = self.das_dir;
+                self.das_frames -= repeat.min(delay);
+            }
+        }
+        if shift != 0 && self.try_move(shift as i32, 0) {
+            self.reset_lock_delay();
+        }
+        if input.hard_drop {
+            if let Some(result) = self.hard_drop() {
+                step.lock = Some(result);
+                return step;
+            }
+        }
+
+        let gravity = self.rules.gravity_rate(self.level, self.speed);
+        let soft_drop = input.soft_drop && self.rules.soft_drop.rows_per_frame() > gravity.rows_per_frame();
+        let rate = if soft_drop { self.rules.soft_drop } else { gravity };
+        let mut blocked = false;
+        for _ in 0..self.gravity_rows(rate) {
+            let moved = if soft_drop { self.soft_drop() } else { self.try_move(0, 1) };
+            if !moved {
+                blocked = true;
+                break;
+            }
+        }
+        self.update_lowest_row();
+
+        let grounded = !self.can_fall();
+        let lock = match self.rules.lock_delay {
+            _ if grounded && input.soft_drop && self.rules.soft_drop_lock => true,
+            None => blocked,
+            Some(delay) if grounded => {
+                self.lock_frames += 1;
+                self.lock_frames >= delay
+            }
+            Some(_) => false,
+        };
+        if lock {
+            step.lock = self.lock();
+        }
+        step
+    }
+
+    fn charge_das(&mut self, held_shift: i8) {
+        let delay = match self.rules.das {
+            Some((delay, _)) => delay,
+            None => return,
+        };
+        if held_shift != self.das_dir {
+            self.das_dir = held_shift;
+            self.das_frames = 0;
+        } else if held_shift != 0 {
+            // Keeps charging between pieces, so the next one can shift right away
+            self.das_frames = (self.das_frames + 1).min(delay);
+        }
+    }
+
+    // Rows to fall this frame
+    fn gravity_rows(&mut self, rate: GravityRate) -> u32 {
+        match rate {
+            GravityRate::FramesPerRow(frames) => {
+                self.gravity_frames += 1;
+                if self.gravity_frames >= frames {
+                    self.gravity_frames = 0;
+                    1
+                } else {
+                    0
+                }
+            }
+            GravityRate::G256(g) => {
+                self.gravity_frames += g;
+                let rows = self.gravity_frames / 256;
+                self.gravity_frames %= 256;
+                rows
+            }
+        }
+    }
+
+    fn can_fall(&self) -> bool {
+        match &self.piece {
+            Some(piece) => check_if_free([piece.pos[0], piece.pos[1] + 1], &piece.matrix, &self.playfield),
+            None => false,
+        }
+    }
+
+    // Falling to a new row always restarts the lock delay
+    fn update_lowest_row(&mut self) {
+        if let Some(piece) = &self.piece {
+            if piece.pos[1] > self.lowest_row {
+                self.lowest_row = piece.pos[1];
+                self.lock_frames = 0;
+                self.lock_resets = 0;
+            }
+        }
+    }
+
+    // Moving or rotating restarts the lock delay, as often as the rules allow
+    fn reset_lock_delay(&mut self) {
+        if let Some(max_resets) = self.rules.lock_resets {
+            if self.lock_resets < max_resets {
+                self.lock_resets += 1;
+                self.lock_frames = 0;
+            }
+        }
+    }
+
+    fn set_level(&mut self, level: u32) {
+        self.level = level;
+        self.rules.update_level(level);
+        self.grading.update(level, self.score, self.time);
+    }
+
+    /// Counts key presses, `finesse_inputs` being the ones that move or rotate the piece
+    pub fn add_inputs(&mut self, keys: u32, finesse_inputs: u32) {
+        self.stats.keys += keys;
+        self.inputs += finesse_inputs;
+    }
+
+    /// Pushes the playfield up and fills the bottom `rows` with garbage, each with a single
+    /// hole. `messiness` is the chance, from 0 to 1, of the hole moving between rows.
+    /// Returns false, and ends the game, if blocks were pushed out of the top.
+    pub fn add_garbage(&mut self, rows: u32, messiness: f32) -> bool {
+        let cols = PLAYFIELD_COLS as usize;
+        let rows = rows.min(PLAYFIELD_ROWS) as usize;
+        if rows == 0 {
+            return true;
+        }
+        let topped_out = self.playfield[..rows * cols].iter().any(|&b| b != 0);
+        self.playfield.copy_within(rows * cols.., 0);
+
+        let first_row = PLAYFIELD_ROWS as usize - rows;
+        for r in first_row..PLAYFIELD_ROWS as usize {
+            let hole = match self.garbage_hole {
+                Some(hole) if self.rng.gen::<f32>() >= messiness => hole,
+                // Always move somewhere else when the hole moves
+                Some(hole) => (hole + self.rng.gen_range(1..PLAYFIELD_COLS as i32)) % PLAYFIELD_COLS as i32,
+                None => self.rng.gen_range(0..PLAYFIELD_COLS as i32),
+            };
+            self.garbage_hole = Some(hole);
+            for c in 0..cols {
+                self.playfield[r * cols + c] = if c as i32 == hole { 0 } else { tetrominos::GARBAGE };
+            }
+        }
+        self.garbage_added += rows as u32;
+
+        if topped_out {
+            info!("Topped out!");
+            self.over = true;
+        }
+        !topped_out
+    }
+
+    /// Number of rows with garbage in them
+    pub fn garbage_rows(&self) -> u32 {
+        self.playfield.chunks(PLAYFIELD_COLS as usize)
+            .filter(|row| row.contains(&tetrominos::GARBAGE))
+            .count() as u32
+    }
+
+    /// Picks a random placement of the active piece, resting on the floor, as the target
+    pub fn set_random_target(&mut self) {
+        let index = match &self.piece {
+            Some(piece) => piece.index,
+            None => return,
+        };
+        let mut placements: Vec<finesse::Footprint> = finesse::placements(index, self.rotation).keys()
+            .cloned()
+            .collect();
+        if placements.is_empty() {
+            return;
+        }
+        // Sorted, so the same seed always gives the same targets
+        placements.sort();
+        let footprint = &placements[self.rng.gen_range(0..placements.len())];
+        let height = footprint.iter().map(|[_, r]| *r).max().unwrap_or(0) + 1;
+        let top = PLAYFIELD_ROWS as i32 - height;
+        self.target = Some(footprint.iter().map(|[c, r]| [*c, r + top]).collect());
+    }
+
+    /// Locks the active piece into the playfield and clears any completed lines
+    pub fn lock(&mut self) -> Option<LockResult> {
+        let piece = self.piece.take()?;
+        let t_spin = piece.index == tetrominos::TT && self.last_rotated && self.is_t_slot(&piece);
+        let optimal_inputs = if self.soft_dropped {
+            None
+        } else {
+            finesse::optimal_inputs(&piece, self.rotation)
+        };
+        let missed_target = self.target.as_ref()
+            .is_some_and(|target| finesse::footprint(target) != finesse::footprint(&piece.cells()));
+        for [c, r] in piece.cells() {
+            if r >= 0 {
+                self.playfield[(r * PLAYFIELD_COLS as i32 + c) as usize] = piece.index;
+            } else {
+                // Locked above the playfield, where nothing can be kept
+                info!("Topped out!");
+                self.over = true;
+            }
+        }
+        let lowest_row = piece.cells().iter().map(|[_, r]| *r).max().unwrap_or(0);
+        let (lines, garbage_lines) = self.clear_lines();
+        self.are = self.rules.entry_delay_frames(lowest_row, lines, self.level);
+        self.combo = if lines > 0 { self.combo + 2 * lines - 2 } else { 1 };
+        self.lines += lines;
+        for _ in 0..garbage_lines {
+            self.garbage_cleared += 1;
+            self.garbage_splits.push(self.time);
+        }
+        while (self.line_splits.len() as u32) < self.lines / SPLIT_LINES {
+            self.line_splits.push(self.time);
+        }
+        self.hold_used = false;
+        let perfect_clear = lines > 0 && self.playfield.iter().all(|&b| b == 0);
+        let result = LockResult {
+            lines,
+            t_spin,
+            perfect_clear,
+            inputs: self.inputs,
+            optimal_inputs,
+            missed_target,
+            garbage_lines,
+            soft_rows: self.soft_rows,
+        };
+        // Scored at the level the lines were cleared on, before any level up
+        self.score += self.rules.score(&result, self.level, self.combo);
+        if lines > 0 {
+            self.set_level(self.rules.next_level(self.level, lines, self.lines));
+        }
+        self.stats.add_lock(piece.index, &result);
+        Some(result)
+    }
+
+    /// Cells outside of the playfield count as occupied
+    pub fn is_occupied(&self, col: i32, row: i32) -> bool {
+        col < 0 || col >= PLAYFIELD_COLS as i32 ||
+        row < 0 || row >= PLAYFIELD_ROWS as i32 ||
+        self.playfield[(row * PLAYFIELD_COLS as i32 + col) as usize] != 0
+    }
+
+    // Three corner rule: at least three of the corners around the center of the T are occupied
+    fn is_t_slot(&self, piece: &Piece) -> bool {
+        let cells = piece.cells();
+        let center = cells.iter().find(|[c, r]| {
+            cells.iter().filter(|[oc, or]| (oc - c).abs() + (or - r).abs() == 1).count() == 3
+        });
+        match center {
+            Some(&[c, r]) => [(-1, -1), (1, -1), (-1, 1), (1, 1)].iter()
+                .filter(|(dc, dr)| self.is_occupied(c + dc, r + dr))
+                .count() >= 3,
+            None => false,
+        }
+    }
+
+    /// Returns the number of cleared lines, and how many of those had garbage in them
+    fn clear_lines(&mut self) -> (u32, u32) {
+        let cols = PLAYFIELD_COLS as usize;
+        let mut cleared = 0;
+        let mut garbage = 0;
+        let mut dst = PLAYFIELD_ROWS as usize;
+        // Walk the rows bottom up, moving every incomplete row down past the cleared ones
+        for src in (0..PLAYFIELD_ROWS as usize).rev() {
+            let row = src * cols..(src + 1) * cols;
+            if self.playfield[row.clone()].iter().all(|&b| b != 0) {
+                cleared += 1;
+                if self.playfield[row].contains(&tetrominos::GARBAGE) {
+                    garbage += 1;
+                }
+                continue;
+            }
+            dst -= 1;
+            if dst != src {
+                self.playfield.copy_within(row, dst * cols);
+            }
+        }
+        for block in self.playfield[..dst * cols].iter_mut() {
+            *block = 0;
+        }
+        (cleared, garbage)
+    }
+}
+
+/// Whether a piece may occupy a cell: inside the walls, above the floor and not taken.
+/// The space above the playfield is open, so pieces can spawn and rotate partly above it.
+pub fn is_free(col: i32, row: i32, blocks: &Playfield) -> bool {
+    col >= 0 && col < PLAYFIELD_COLS as i32 && row < PLAYFIELD_ROWS as i32 &&
+    (row < 0 || blocks[(row * PLAYFIELD_COLS as i32 + col) as usize] == 0)
+}
+
+pub fn check_if_free(pos: [i32 ; 2], tetro_shape: &TetroShape, blocks: &Playfield) -> bool {
+    for r in 0..4usize {
+        let offset_row = r as i32 + pos[1];
+        let block_row = offset_row * PLAYFIELD_COLS as i32;
+        for c in 0..4usize {
+            let offset_col = c as i32 + pos[0];
+            if match tetro_shape {
+                TetroShape::Odd(t) if c < 3 && r < 3 => t[r][c],
+                TetroShape::Even(t) => t[r][c],
+                _ => false
+            } && (
+                // Check if outside play field, which is open at the top
+                offset_row >= PLAYFIELD_ROWS as i32 ||
+                offset_col < 0 || offset_col >= PLAYFIELD_COLS as i32 ||
+                // Check if target block is occupied
+                (offset_row >= 0 && blocks[block_row as usize + (offset_col as usize)] != 0)) { return false; }
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::puzzle::parse_field;
+    use crate::rotation::Rotation;
+    use crate::rules::EntryDelay;
+    use crate::tetrominos::{TI, TO, TT};
+
+    fn game(rows: &str, sequence: &[Tetromino]) -> Game {
+        Game::with_seed(parse_field(rows).unwrap(), Some(sequence), 1)
+    }
+
+    /// A flat I with its blocks on `row`
+    fn i_on_row(rotation: &dyn RotationSystem, row: i32) -> Piece {
+        let mut piece = Piece::new(TI, rotation);
+        piece.pos[1] += row - piece.cells()[0][1];
+        piece
+    }
+
+    /// A T in orientation `rot` with the middle of its three-block side on `center`
+    fn t_at(rot: u8, [col, row]: [i32; 2]) -> Piece {
+        let srs = Rotation::Srs.system();
+        let mut piece = Piece { index: TT, pos: [0, 0], rot, matrix: srs.shape(TT, rot) };
+        let cells = piece.cells();
+        let &[c, r] = cells.iter()
+            .find(|[c, r]| cells.iter().filter(|[oc, or]| (oc - c).abs() + (or - r).abs() == 1).count() == 3)
+            .unwrap();
+        piece.pos = [col - c, row - r];
+        piece
+    }
+
+    #[test]
+    fn clears_full_rows_and_drops_the_rest() {
+        let mut single = game("
+            ....I.....
+            IIIIIIIIII
+            XXXXX.XXXX", &[]);
+        assert_eq!(single.clear_lines(), (1, 0));
+        assert_eq!(single.playfield[14 * 10 + 4], TI);
+        assert_eq!(single.playfield[15 * 10 + 5], 0);
+        assert_eq!(single.playfield[13 * 10 + 4], 0);
+
+        let mut tetris = game("
+            I.........
+            XXXXXXXXXX
+            IIIIIIIIII
+            XXXXXXXXXX
+            IIIIIIIIII", &[]);
+        assert_eq!(tetris.clear_lines(), (4, 2));
+        assert_eq!(tetris.playfield[15 * 10], TI);
+        assert_eq!(tetris.playfield.iter().filter(|&&b| b != 0).count(), 1);
+    }
+
+    fn holes(g: &Game, rows: std::ops::Range<usize>) -> Vec<usize> {
+        rows.map(|r| {
+            let row = &g.playfield[r * 10..(r + 1) * 10];
+            assert_eq!(row.iter().filter(|&&b| b == 0).count(), 1, "row {} has one hole", r);
+            row.iter().position(|&b| b == 0).unwrap()
+        }).collect()
+    }
+
+    #[test]
+    fn garbage_rows_have_one_hole() {
+        let mut tidy = game("", &[]);
+        assert!(tidy.add_garbage(8, 0.0));
+        let tidy_holes = holes(&tidy, 8..16);
+        assert!(tidy_holes.iter().all(|&hole| hole == tidy_holes[0]));
+
+        let mut messy = game("", &[]);
+        assert!(messy.add_garbage(8, 1.0));
+        let messy_holes = holes(&messy, 8..16);
+        assert!(messy_holes.windows(2).all(|pair| pair[0] != pair[1]));
+        assert_eq!(messy.garbage_rows(), 8);
+    }
+
+    #[test]
+    fn garbage_pushes_the_stack_up() {
+        let mut g = game("IIII......", &[]);
+        assert!(g.add_garbage(2, 0.0));
+        assert_eq!(g.playfield[13 * 10], TI);
+        assert!(g.add_garbage(0, 0.0));
+        assert_eq!(g.garbage_added, 2);
+
+        // Pushing blocks out of the top ends the game
+        assert!(!g.add_garbage(PLAYFIELD_ROWS, 0.0));
+        assert!(g.over);
+        assert_eq!(g.garbage_added, 2 + PLAYFIELD_ROWS);
+    }
+
+    #[test]
+    fn cleared_garbage_is_counted_and_timed() {
+        let mut g = game("", &[TI]);
+        assert!(g.add_garbage(1, 0.0));
+        let hole = holes(&g, 15..16)[0];
+        g.playfield[15 * 10 + hole] = TI;
+        g.time = 2.5;
+        g.piece = Some(i_on_row(g.rotation, 0));
+        let lock = g.lock().unwrap();
+        assert_eq!((lock.lines, lock.garbage_lines), (1, 1));
+        assert_eq!(g.garbage_cleared, 1);
+        assert_eq!(g.garbage_splits, vec![2.5]);
+        assert!(g.line_splits.is_empty());
+    }
+
+    #[test]
+    fn t_slots_need_three_corners() {
+        let slot = game("
+            X.........
+            ..........
+            X.X.......", &[]);
+        assert!(slot.is_t_slot(&t_at(2, [1, 14])));
+        let open = game("
+            ..........
+            ..........
+            X.X.......", &[]);
+        assert!(!open.is_t_slot(&t_at(2, [1, 14])));
+    }
+
+    #[test]
+    fn walls_and_the_floor_count_as_corners() {
+        let empty = game("", &[]);
+        let blocked = game("
+            .X........
+            ..........
+            ..........
+            .....X....", &[]);
+        // Against the left wall, the two corners outside of it are taken
+        assert!(!empty.is_t_slot(&t_at(1, [0, 13])));
+        assert!(blocked.is_t_slot(&t_at(1, [0, 13])));
+        // Flat on the floor, the two corners below it are taken
+        assert!(!empty.is_t_slot(&t_at(0, [5, 15])));
+        assert!(game("....X.....\n..........", &[]).is_t_slot(&t_at(0, [5, 15])));
+    }
+
+    #[test]
+    fn hold_swaps_once_per_piece() {
+        let mut g = game("", &[TI, TO, TT]);
+        assert!(g.spawn());
+        assert!(g.hold());
+        assert_eq!(g.piece.as_ref().unwrap().index, TO);
+        assert_eq!(g.hold, Some(TI));
+        assert!(!g.hold());
+        assert_eq!(g.piece.as_ref().unwrap().index, TO);
+
+        g.hard_drop().unwrap();
+        assert!(g.spawn());
+        assert!(g.hold());
+        assert_eq!(g.piece.as_ref().unwrap().index, TI);
+        assert_eq!(g.hold, Some(TT));
+    }
+
+    #[test]
+    fn locking_above_the_playfield_tops_out() {
+        let mut g = game("", &[TI]);
+        g.piece = Some(i_on_row(g.rotation, 15));
+        g.lock().unwrap();
+        assert!(!g.over);
+
+        g.piece = Some(i_on_row(g.rotation, -1));
+        g.lock().unwrap();
+        assert!(g.over);
+    }
+
+    #[test]
+    fn the_next_piece_waits_out_the_entry_delay() {
+        let mut g = game("", &[TI, TO]);
+        g.set_rules(Rules { entry_delay: EntryDelay::Frames(3), ..Rules::default() });
+        assert!(g.step(&FrameInput::default()).spawned);
+        let drop = FrameInput { hard_drop: true, ..FrameInput::default() };
+        assert!(g.step(&drop).lock.is_some());
+        for _ in 0..3 {
+            assert!(!g.step(&FrameInput::default()).spawned);
+            assert!(g.piece.is_none());
+        }
+        assert!(g.step(&FrameInput::default()).spawned);
+    }
+
+    #[test]
+    fn held_shifts_repeat_once_das_has_charged() {
+        let mut g = game("", &[TI]);
+        g.set_rules(Rules { das: Some((10, 2)), ..Rules::default() });
+        let col = |g: &Game| g.piece.as_ref().unwrap().pos[0];
+        g.step(&FrameInput { shift: 1, held_shift: 1, ..FrameInput::default() });
+        assert_eq!(col(&g), 4);
+        let held = FrameInput { held_shift: 1, ..FrameInput::default() };
+        let cols: Vec<i32> = (0..12).map(|_| { g.step(&held); col(&g) }).collect();
+        assert_eq!(cols, vec![4, 4, 4, 4, 4, 4, 4, 4, 4, 5, 5, 6]);
+    }
+
+    #[test]
+    fn resting_pieces_lock_after_the_lock_delay() {
+        let mut g = game("", &[TI, TO]);
+        assert!(g.place_piece(i_on_row(g.rotation, 15)));
+        for _ in 0..19 {
+            assert!(g.step(&FrameInput::default()).lock.is_none());
+        }
+        // Moving restarts the delay, the frame of the move being the first of it
+        assert!(g.step(&FrameInput { shift: 1, ..FrameInput::default() }).lock.is_none());
+        for _ in 0..28 {
+            assert!(g.step(&FrameInput::default()).lock.is_none());
+        }
+        assert!(g.step(&FrameInput::default()).lock.is_some());
+    }
+}
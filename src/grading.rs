@@ -0,0 +1,97 @@
+use serde::{Serialize, Deserialize};
+
+/// Score needed for each grade of the master mode, from 9 up to S9
+const GRADES: [(u32, &str); 18] = [
+    (0, "9"), (400, "8"), (800, "7"), (1400, "6"), (2000, "5"), (3500, "4"), (5500, "3"),
+    (8000, "2"), (12000, "1"), (16000, "S1"), (22000, "S2"), (30000, "S3"), (40000, "S4"),
+    (52000, "S5"), (66000, "S6"), (82000, "S7"), (100000, "S8"), (120000, "S9"),
+];
+
+/// Levels where the Grand Master requirements are checked: (level, score, seconds)
+const GM_CHECKPOINTS: [(u32, u32, f32); 3] = [
+    (300, 12000, 4.0 * 60.0 + 15.0),
+    (500, 40000, 7.0 * 60.0 + 30.0),
+    (999, 126000, 13.0 * 60.0 + 30.0),
+];
+
+/// Grade of a master mode game. The grade follows the score, except for Grand Master which
+/// also needs the game to be on pace at every checkpoint.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Grading {
+    gm_possible: bool,
+    checkpoints_passed: usize,
+}
+
+impl Default for Grading {
+    fn default() -> Self {
+        Grading { gm_possible: true, checkpoints_passed: 0 }
+    }
+}
+
+impl Grading {
+    /// Checks the checkpoints reached at `level`
+    pub fn update(&mut self, level: u32, score: u32, time_secs: f32) {
+        while let Some(&(at, min_score, max_time)) = GM_CHECKPOINTS.get(self.checkpoints_passed) {
+            if level < at {
+                break;
+            }
+            if score < min_score || time_secs > max_time {
+                self.gm_possible = false;
+            }
+            self.checkpoints_passed += 1;
+        }
+    }
+
+    pub fn grade(&self, score: u32) -> &'static str {
+        if self.gm_possible && self.checkpoints_passed == GM_CHECKPOINTS.len() {
+            return "GM";
+        }
+        GRADES.iter()
+            .take_while(|(min_score, _)| *min_score <= score)
+            .last()
+            .map_or("9", |(_, grade)| grade)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grades_follow_the_score() {
+        let grading = Grading::default();
+        assert_eq!(grading.grade(0), "9");
+        assert_eq!(grading.grade(399), "9");
+        assert_eq!(grading.grade(400), "8");
+        assert_eq!(grading.grade(12000), "1");
+        assert_eq!(grading.grade(16000), "S1");
+        assert_eq!(grading.grade(1_000_000), "S9");
+    }
+
+    #[test]
+    fn grand_master_needs_every_checkpoint_on_pace() {
+        let mut grading = Grading::default();
+        grading.update(300, 12000, 4.0 * 60.0);
+        grading.update(500, 40000, 7.0 * 60.0);
+        assert_eq!(grading.grade(126000), "S9");
+        grading.update(999, 126000, 13.0 * 60.0);
+        assert_eq!(grading.grade(126000), "GM");
+    }
+
+    #[test]
+    fn a_slow_checkpoint_rules_out_grand_master() {
+        let mut grading = Grading::default();
+        grading.update(300, 12000, 5.0 * 60.0);
+        grading.update(500, 40000, 7.0 * 60.0);
+        grading.update(999, 126000, 13.0 * 60.0);
+        assert_eq!(grading.grade(126000), "S9");
+    }
+
+    #[test]
+    fn skipped_checkpoints_are_checked_together() {
+        let mut grading = Grading::default();
+        grading.update(600, 10000, 60.0);
+        grading.update(999, 200000, 60.0);
+        assert_eq!(grading.grade(200000), "S9");
+    }
+}
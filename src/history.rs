@@ -0,0 +1,108 @@
+use crate::game::Game;
+
+/// Snapshots of a game to step backwards and forwards through, one for every locked piece
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Game>,
+    redo: Vec<Game>,
+    // The game as it was when the active piece spawned
+    current: Option<Game>,
+}
+
+impl History {
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.current = None;
+    }
+
+    /// Remembers `game` as the state to return to if the piece that just spawned is undone
+    pub fn spawned(&mut self, game: &Game) {
+        self.current = Some(game.clone());
+    }
+
+    /// Takes the snapshot for a locked piece. Anything undone before is dropped.
+    pub fn locked(&mut self) {
+        if let Some(game) = self.current.take() {
+            self.undo.push(game);
+            self.redo.clear();
+        }
+    }
+
+    /// The game as it was before the last locked piece, if there is one
+    pub fn undo(&mut self, game: &Game) -> Option<Game> {
+        let previous = self.undo.pop()?;
+        self.redo.push(self.current.take().unwrap_or_else(|| game.clone()));
+        self.current = Some(previous.clone());
+        Some(previous)
+    }
+
+    /// The game as it was before the last undo, if nothing was locked since
+    pub fn redo(&mut self, game: &Game) -> Option<Game> {
+        let next = self.redo.pop()?;
+        self.undo.push(self.current.take().unwrap_or_else(|| game.clone()));
+        self.current = Some(next.clone());
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Games told apart by their line count
+    fn game(lines: u32) -> Game {
+        let mut game = Game::new();
+        game.lines = lines;
+        game
+    }
+
+    fn lines(game: Option<Game>) -> Option<u32> {
+        game.map(|g| g.lines)
+    }
+
+    #[test]
+    fn steps_back_and_forth_through_locked_pieces() {
+        let mut history = History::default();
+        history.spawned(&game(0));
+        history.locked();
+        history.spawned(&game(1));
+        history.locked();
+        history.spawned(&game(2));
+
+        assert_eq!(lines(history.undo(&game(2))), Some(1));
+        assert_eq!(lines(history.undo(&game(1))), Some(0));
+        assert_eq!(lines(history.undo(&game(0))), None);
+        assert_eq!(lines(history.redo(&game(0))), Some(1));
+        assert_eq!(lines(history.redo(&game(1))), Some(2));
+        assert_eq!(lines(history.redo(&game(2))), None);
+    }
+
+    #[test]
+    fn locking_drops_what_was_undone() {
+        let mut history = History::default();
+        history.spawned(&game(0));
+        history.locked();
+        history.spawned(&game(1));
+        history.locked();
+        history.spawned(&game(2));
+        assert_eq!(lines(history.undo(&game(2))), Some(1));
+
+        // Playing on from the undone state
+        history.locked();
+        history.spawned(&game(5));
+        assert_eq!(lines(history.redo(&game(5))), None);
+        assert_eq!(lines(history.undo(&game(5))), Some(1));
+        assert_eq!(lines(history.undo(&game(1))), Some(0));
+    }
+
+    #[test]
+    fn clearing_forgets_everything() {
+        let mut history = History::default();
+        history.spawned(&game(0));
+        history.locked();
+        history.clear();
+        assert!(history.undo(&game(1)).is_none());
+        assert!(history.redo(&game(1)).is_none());
+    }
+}
@@ -8,5 +8,20 @@ pub mod paths;
 pub mod tetrominos;
 pub mod engine;
 pub mod config;
+pub mod game;
+pub mod puzzle;
+pub mod modes;
+pub mod fumen;
+pub mod scores;
+pub mod stats;
+pub mod finesse;
+pub mod rotation;
+pub mod rules;
+pub mod grading;
+pub mod solver;
+pub mod opener;
+pub mod history;
+pub mod save;
+pub mod replay;
 
 pub type Result<T> = result::Result<T, Box<dyn Error>>;
\ No newline at end of file
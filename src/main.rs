@@ -8,8 +8,6 @@ use lyon::tessellation::geometry_builder::*;
 use lyon::tessellation::{FillOptions, FillTessellator};
 use lyon::tessellation::{StrokeOptions, StrokeTessellator};
 
-use lyon::algorithms::walk;
-
 use winit::dpi::PhysicalSize;
 use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent, MouseScrollDelta};
 use winit::event_loop::{ControlFlow, EventLoop};
@@ -29,7 +27,10 @@ use ruzzle::tetrominos::{Tetromino, TetroShape};
 use rand::Rng;
 use ruzzle::{tetrominos};
 use ruzzle::engine::*;
-use log::{Log, info};
+use ruzzle::modes::{Mode, DIG_MESSINESS};
+use ruzzle::rotation::Rotation;
+use ruzzle::fumen;
+use log::{Log, info, error};
 
 #[macro_use]
 extern crate lazy_static;
@@ -41,12 +42,6 @@ extern crate lazy_static;
 const DEFAULT_WINDOW_WIDTH: f32 = 1024.0;
 const DEFAULT_WINDOW_HEIGHT: f32 = 768.0;
 
-const PLAYFIELD_COLS: u32 = 10;
-const PLAYFIELD_ROWS: u32 = 16; // 16
-const PLAYFIELD_SIZE: u32 = PLAYFIELD_COLS * PLAYFIELD_ROWS;
-const TETRION_SIZE: f32 = 8.0;
-const MAX_CURR_SIZE:usize = 16;
-
 // Number of samples for anti-aliasing
 // Set to 1 to disable
 const SAMPLE_COUNT: u32 = 4;
@@ -66,19 +61,37 @@ fn main() {
     println!(" https://github.com/piksel/ruzzle");
     println!();
     println!(" Controls:");
-    println!("   Arrow keys  : move current tetromino");
+    println!("   Left/Right  : move current tetromino");
+    println!("   Down        : soft drop");
+    println!("   Up          : hard drop");
     println!("   X/Space     : rotate current tetromino clockwise");
     println!("   Z/Backspace : rotate current tetromino counter clockwise");
     println!("   PgUp/PgDown : zoom in/out (or mouse wheel)");
+    println!("   C/Shift     : hold");
     println!("           +/- : increase/decrease level");
+    println!("   Up/Down     : choose mode or puzzle");
+    println!("   Return      : start");
+    println!("   F           : log the current board as a fumen");
+    println!("   Tab         : toggle the stats (PPS, APM, KPP, pieces) in the title");
+    println!("   H           : toggle perfect clear hints");
+    println!("   U/R         : undo/redo a piece in openers and puzzles");
+    println!("   Escape      : pause/resume (quit from the menu)");
+    println!("   Q           : quit, saving a game in progress to resume next time");
+    println!();
+    println!(" Options:");
+    println!("   --fumen <data>     : start from a fumen setup");
+    println!("   --fumen-page <n>   : fumen page to start from (default 1)");
+    println!("   --rotation <name>  : free play with srs, ars, nes, sega or matrix rotation");
+    println!("   --dig <rows>       : dig race through this many rows of garbage");
+    println!("   --messiness <0-1>  : chance of the garbage hole moving (default 0.2)");
+    println!("   --seed <n>         : seed for the dig race garbage and pieces");
     println!();
 
     let mut engine = Engine::new(
         config.graphics.sample_count,
         config.graphics.tolerance,
         config.graphics.use_low_power_gpu);
-
-    let num_instances: usize = PLAYFIELD_SIZE as usize + MAX_CURR_SIZE;
+    engine.set_gpu_grid(config.graphics.gpu_grid);
 
     let tetrion_path_scale = 0.8;
     //build_tetrion_path(&mut builder);
@@ -87,70 +100,98 @@ fn main() {
     build_tetrion_path(&mut builder);
     let path = builder.build();
 
-    engine.create_geo_entity(
-        &path, num_instances, true, true, tetrion_path_scale
-    );
+    let board_style = StrokeStyle {
+        line_join: tessellation::LineJoin::Round,
+        ..StrokeStyle::default()
+    };
+    let board = engine.create_geo_entity(
+        &path, BOARD_INSTANCES, true, Some(board_style.clone()), tetrion_path_scale
+    ).expect("Failed to build the board");
+    engine.entity(&board).expect("Board was just added").set_layer(Layer::Field, 0);
+    engine.set_board(board).expect("Board has too few instances");
+
+    // The personal best replayed beside the board
+    let ghost_board = engine.create_geo_entity(
+        &path, GHOST_BOARD_INSTANCES, true, Some(board_style), tetrion_path_scale
+    ).expect("Failed to build the ghost board");
+    engine.entity(&ghost_board).expect("Ghost board was just added").set_layer(Layer::Field, 0);
+    engine.set_ghost_board(ghost_board).expect("Ghost board has too few instances");
+
+    // Two dashes along each side of a cell
+    let outline_style = StrokeStyle {
+        line_join: tessellation::LineJoin::Round,
+        dashes: Some(vec![2.5, 2.5]),
+        ..StrokeStyle::default()
+    };
+    let outline = engine.create_geo_entity(
+        &path, OUTLINE_INSTANCES, false, Some(outline_style), tetrion_path_scale
+    ).expect("Failed to build the outlines");
+    engine.entity(&outline).expect("Outlines were just added").set_layer(Layer::Field, 1);
+    engine.set_outline(outline).expect("Outlines have too few instances");
 
     engine.create_bg_entity(
         2.0,
     );
 
-    // /!\ No more entities can be added after this step
+    // Entities can still be added and removed after this
     engine.init_render();
 
 
-    let mut index = 0;
+    engine.init_game();
 
-    // Set up the initial play field
-    for r in 0..PLAYFIELD_ROWS as usize {
-        let rb = r / 4;
-        let ri = r % 4;
-        for c in 0..PLAYFIELD_COLS as usize {
-            // Hacky way to show all tetrominos; flip if past middle to add "padding"
-            let tetind = if c < 5 { rb } else { 4 + rb };
-            let ci = if c < 5 { c } else { (PLAYFIELD_COLS as usize - c)-1 };
-            let tetromino = &tetrominos::ALL[tetind];
-            engine.playfield[index] = if tetromino.is_solid(ci, ri) { tetind } else { 0 };
-            index += 1;
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(data) = arg_value(&args, "--fumen") {
+        let page = arg_value(&args, "--fumen-page")
+            .and_then(|p| p.parse::<usize>().ok())
+            .unwrap_or(1);
+        match fumen::decode(data) {
+            Ok(pages) => match Mode::from_fumen(&pages, page.saturating_sub(1)) {
+                Some(mode) => engine.add_mode(mode),
+                None => error!("Fumen only has {} pages", pages.len()),
+            },
+            Err(err) => error!("Failed to load fumen: {}", err),
         }
     }
-
-    engine.init_game();
-
-
-    // Update entities from play field
-    for geo in engine.geo_entities.iter_mut() {
-        for (idx, (prim, tet)) in geo.primitives
-            .iter_mut()
-            .zip(engine.playfield.iter())
-            .enumerate()
-            .take(PLAYFIELD_SIZE as usize)
-        {
-            let color = tetrominos::Colors[*tet];
-            let idf = idx as f32;
-            let col = idf % (PLAYFIELD_COLS as f32);
-            let row = (idf / (PLAYFIELD_COLS as f32)).floor();
-            prim.translate = [
-                col * TETRION_SIZE,
-                row * TETRION_SIZE,
-            ];
-            prim.z_index = (idx as u32 + 1) as i32;
-
-            if *tet == 0 {
-                prim.color = [0.0, 0.0, 0.0, 1.0];
-            } else {
-                prim.color = color;
-            }
-
-            prim.scale = tetrion_path_scale;
-            prim.width = 0.3;
+    if let Some(name) = arg_value(&args, "--rotation") {
+        match Rotation::from_name(name) {
+            Some(rotation) => engine.add_mode(Mode::Free(rotation)),
+            None => error!("Unknown rotation system {:?}", name),
+        }
+    }
+    if let Some(rows) = arg_value(&args, "--dig") {
+        match dig_mode(rows, arg_value(&args, "--messiness"), arg_value(&args, "--seed")) {
+            Ok(mode) => engine.add_mode(mode),
+            Err(err) => error!("{}", err),
         }
-
-
     }
-
 
     info!("Starting main loop!");
     engine.run();
 }
 
+/// The dig race asked for with `--dig`, `--messiness` and `--seed`
+fn dig_mode(rows: &str, messiness: Option<&str>, seed: Option<&str>) -> Result<Mode, String> {
+    let rows = match rows.parse::<u32>() {
+        Ok(rows) if rows > 0 => rows,
+        _ => return Err(format!("Invalid number of dig rows: {:?}", rows)),
+    };
+    let messiness = match messiness {
+        None => DIG_MESSINESS,
+        Some(m) => match m.parse::<f32>() {
+            Ok(messiness) if messiness.is_finite() => messiness,
+            _ => return Err(format!("Invalid garbage messiness: {:?}", m)),
+        },
+    };
+    let seed = match seed {
+        None => None,
+        Some(s) => Some(s.parse::<u32>().map_err(|_| format!("Invalid seed: {:?}", s))?),
+    };
+    Ok(Mode::dig(rows, messiness, seed))
+}
+
+fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|a| a == name)
+        .and_then(|i| args.get(i + 1))
+        .map(|v| v.as_str())
+}
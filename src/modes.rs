@@ -0,0 +1,326 @@
+use crate::game::{Game, LockResult, Piece, Playfield, PLAYFIELD_SIZE};
+use crate::opener::{self, Opener};
+use crate::puzzle::{self, Puzzle};
+use crate::fumen;
+use crate::rotation::Rotation;
+use crate::rules::{Randomizer, Rules, MASTER_MAX_LEVEL};
+use crate::scores::Ranking;
+use crate::tetrominos::Tetromino;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Outcome {
+    Success,
+    Failure,
+}
+
+// Placements in a round of the finesse trainer
+const TRAINER_PIECES: u32 = 20;
+// Lines to clear in a sprint
+const SPRINT_LINES: u32 = 40;
+// Garbage rows on the board at once in a dig race, the rest comes in as they are cleared
+const DIG_VISIBLE_ROWS: u32 = 10;
+/// Chance of the garbage hole moving between rows, unless another is asked for
+pub const DIG_MESSINESS: f32 = 0.2;
+
+#[derive(Clone)]
+pub enum Mode {
+    Free(Rotation),
+    /// Race to clear 40 lines, with splits against the personal best
+    Sprint,
+    /// Random target placements on an empty board
+    FinesseTrainer,
+    /// Endless play with the NES rules, from the given level
+    Classic(u32),
+    /// TGM style master mode: reach level 999, graded on score and pace
+    Master,
+    /// Practice building an opener, with the next placement shown on the field
+    Opener(Opener),
+    Puzzle(Puzzle),
+    /// Race to clear `rows` rows of garbage
+    Dig {
+        name: String,
+        rows: u32,
+        messiness: f32,
+        seed: Option<u32>,
+    },
+    /// Field, piece and sequence imported from a fumen
+    Fumen {
+        /// Tells the diagrams apart, for their scores and saved games
+        name: String,
+        playfield: Playfield,
+        /// Where the piece of the page is, in play from the start
+        piece: Option<Piece>,
+        sequence: Vec<Tetromino>,
+    },
+}
+
+impl Mode {
+    pub fn name(&self) -> &str {
+        match self {
+            Mode::Free(Rotation::Srs) => "Free play",
+            Mode::Free(Rotation::Matrix) => "Free play (Matrix)",
+            Mode::Free(Rotation::Ars) => "Free play (ARS)",
+            Mode::Free(Rotation::Nes) => "Free play (NES)",
+            Mode::Free(Rotation::Sega) => "Free play (Sega)",
+            Mode::Sprint => "Sprint",
+            Mode::FinesseTrainer => "Finesse trainer",
+            Mode::Classic(0) => "Classic",
+            Mode::Classic(18) => "Classic 18",
+            Mode::Classic(19) => "Classic 19",
+            Mode::Classic(29) => "Classic 29",
+            Mode::Classic(_) => "Classic (custom level)",
+            Mode::Master => "Master",
+            Mode::Opener(opener) => &opener.name,
+            Mode::Dig { name, .. } => name,
+            Mode::Puzzle(puzzle) => &puzzle.name,
+            Mode::Fumen { name, .. } => name,
+        }
+    }
+
+    pub fn description(&self) -> &str {
+        match self {
+            Mode::Free(_) => "Endless play at the selected level",
+            Mode::Sprint => "Clear 40 lines as fast as possible",
+            Mode::FinesseTrainer => "Place each piece on its target using as few keys as possible",
+            Mode::Classic(_) => "NES rules: no hold, no hard drop, one preview",
+            Mode::Master => "Reach level 999 as the speed goes up to 20G",
+            Mode::Opener(opener) => &opener.description,
+            Mode::Dig { .. } => "Clear all the garbage as fast as possible",
+            Mode::Puzzle(puzzle) => &puzzle.description,
+            Mode::Fumen { .. } => "Setup imported from a fumen",
+        }
+    }
+
+    pub fn rules(&self) -> Rules {
+        match self {
+            Mode::Free(rotation) => Rules::guideline(*rotation),
+            Mode::FinesseTrainer => Rules { hold: false, ..Rules::default() },
+            Mode::Classic(level) => Rules::nes(*level),
+            Mode::Master => Rules::master(),
+            Mode::Opener(_) => Rules { randomizer: Randomizer::Bag, ..Rules::default() },
+            Mode::Puzzle(puzzle) => Rules::guideline(puzzle.rotation),
+            Mode::Sprint | Mode::Dig { .. } | Mode::Fumen { .. } => Rules::default(),
+        }
+    }
+
+    pub fn new_game(&self) -> Game {
+        let mut game = match self {
+            Mode::Free(_) | Mode::Sprint | Mode::FinesseTrainer | Mode::Classic(_) | Mode::Master |
+            Mode::Opener(_) => Game::new(),
+            Mode::Dig { rows, messiness, seed, .. } => {
+                let playfield = [0; PLAYFIELD_SIZE as usize];
+                let mut game = match seed {
+                    Some(seed) => Game::with_seed(playfield, None, *seed),
+                    None => Game::with_setup(playfield, None),
+                };
+                game.add_garbage((*rows).min(DIG_VISIBLE_ROWS), *messiness);
+                game
+            }
+            Mode::Puzzle(puzzle) => Game::with_setup(puzzle.playfield, Some(&puzzle.sequence)),
+            Mode::Fumen { playfield, piece: None, sequence, .. } if sequence.is_empty() =>
+                Game::with_setup(*playfield, None),
+            Mode::Fumen { playfield, sequence, .. } => Game::with_setup(*playfield, Some(sequence)),
+        };
+        game.set_rules(self.rules());
+        if let Mode::Fumen { piece: Some(piece), .. } = self {
+            game.place_piece(piece.clone());
+        }
+        game
+    }
+
+    /// A dig race through at least one row of garbage. Scores are kept per mode name, so
+    /// the name tells apart races with other garbage.
+    pub fn dig(rows: u32, messiness: f32, seed: Option<u32>) -> Mode {
+        let rows = rows.max(1);
+        let messiness = if messiness.is_nan() { DIG_MESSINESS } else { messiness.clamp(0.0, 1.0) };
+        let name = match seed {
+            Some(seed) => format!("Dig race {}, messiness {}, seed {}", rows, messiness, seed),
+            None => format!("Dig race {}, messiness {}", rows, messiness),
+        };
+        Mode::Dig { name, rows, messiness, seed }
+    }
+
+    /// Starts from the field and piece of page `start`, dealing the pieces of the following
+    /// pages
+    pub fn from_fumen(pages: &[fumen::Page], start: usize) -> Option<Mode> {
+        let page = pages.get(start)?;
+        Some(Mode::Fumen {
+            name: format!("Fumen {}", fumen::fingerprint(&pages[start..])),
+            playfield: page.playfield,
+            piece: page.piece.clone(),
+            sequence: pages[start + 1..].iter()
+                .filter_map(|p| p.piece.as_ref().map(|piece| piece.index))
+                .collect(),
+        })
+    }
+
+    pub fn ranking(&self) -> Ranking {
+        match self {
+            Mode::Free(_) | Mode::Classic(_) | Mode::Master => Ranking::Score,
+            Mode::Sprint | Mode::FinesseTrainer | Mode::Opener(_) | Mode::Dig { .. } | Mode::Puzzle(_) |
+            Mode::Fumen { .. } => Ranking::Time,
+        }
+    }
+
+    /// Whether a finished game should be added to the score table
+    pub fn records(&self, outcome: Outcome) -> bool {
+        match self {
+            Mode::Free(_) | Mode::Classic(_) | Mode::Master => true,
+            Mode::Sprint | Mode::FinesseTrainer | Mode::Opener(_) | Mode::Dig { .. } | Mode::Puzzle(_) |
+            Mode::Fumen { .. } => outcome == Outcome::Success,
+        }
+    }
+
+    /// Practice modes can step back and forth through the placed pieces
+    pub fn is_practice(&self) -> bool {
+        matches!(self, Mode::Opener(_) | Mode::Puzzle(_) | Mode::Fumen { .. })
+    }
+
+    /// Whether the splits and a replay are kept, to race the personal best
+    pub fn races_pb(&self) -> bool {
+        matches!(self, Mode::Sprint)
+    }
+
+    /// Called whenever a new piece has been spawned from the queue
+    pub fn on_spawn(&self, game: &mut Game) {
+        match self {
+            Mode::FinesseTrainer => {
+                game.playfield = [0; PLAYFIELD_SIZE as usize];
+                game.set_random_target();
+            }
+            Mode::Opener(opener) => {
+                // A piece with no place in the opener should be held, an empty target
+                // makes any placement of it a miss. Holding is no way out when the held
+                // piece has no place either, like the second T of a two bag opener, so
+                // then the piece can go anywhere.
+                let place = |index| opener.next_placement(&game.playfield, index);
+                let stuck = game.hold.is_some_and(|held| place(held).is_none());
+                let index = game.piece.as_ref().map(|p| p.index);
+                game.target = index.and_then(|index| match place(index) {
+                    None if stuck => None,
+                    target => Some(target.unwrap_or_default()),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    /// Status shown when a piece misses its target
+    pub fn miss_message(&self) -> &str {
+        match self {
+            Mode::Opener(_) => "Off the opener",
+            _ => "Missed the target",
+        }
+    }
+
+    /// Called after every locked piece, before `check`
+    pub fn on_lock(&self, game: &mut Game) {
+        if let Mode::Dig { rows, messiness, .. } = self {
+            let missing = DIG_VISIBLE_ROWS.saturating_sub(game.garbage_rows())
+                .min(rows - game.garbage_added);
+            game.add_garbage(missing, *messiness);
+        }
+    }
+
+    /// Called after every locked piece to see if the game has been decided
+    pub fn check(&self, game: &Game, lock: &LockResult) -> Option<Outcome> {
+        match self {
+            Mode::Free(_) | Mode::Classic(_) => None,
+            Mode::Sprint if game.lines >= SPRINT_LINES => Some(Outcome::Success),
+            Mode::Sprint => None,
+            Mode::Master if game.level >= MASTER_MAX_LEVEL => Some(Outcome::Success),
+            Mode::Master => None,
+            Mode::FinesseTrainer if game.stats.pieces >= TRAINER_PIECES => Some(Outcome::Success),
+            Mode::FinesseTrainer => None,
+            Mode::Opener(opener) if opener.stage(&game.playfield).is_none() => Some(Outcome::Success),
+            Mode::Opener(_) => None,
+            Mode::Dig { rows, .. } if game.garbage_cleared >= *rows => Some(Outcome::Success),
+            Mode::Dig { .. } => None,
+            Mode::Puzzle(puzzle) if puzzle.goal.is_met(game, lock) => Some(Outcome::Success),
+            Mode::Puzzle(_) | Mode::Fumen { .. } => None,
+        }
+    }
+}
+
+/// All selectable modes: free play, sprint, classic, master, the trainer and a dig race, followed by the
+/// openers and every available puzzle
+pub fn load_modes() -> Vec<Mode> {
+    let mut modes = vec![
+        Mode::Free(Rotation::Srs),
+        Mode::Sprint,
+        Mode::Classic(0),
+        Mode::Classic(18),
+        Mode::Master,
+        Mode::FinesseTrainer,
+        Mode::dig(10, 0.2, None),
+    ];
+    modes.extend(opener::load_openers().into_iter().map(Mode::Opener));
+    modes.extend(puzzle::load_puzzles().into_iter().map(Mode::Puzzle));
+    modes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tetrominos;
+
+    #[test]
+    fn fumen_keeps_the_piece_of_the_page() {
+        // A T at the bottom, then an empty page with an I
+        let pages = fumen::decode("v115@vhAVQJ").unwrap();
+        let mut next = pages[0].clone();
+        next.piece = Some(Piece::new(tetrominos::TI, Rotation::default().system()));
+        let pages = vec![pages[0].clone(), next];
+
+        let mode = Mode::from_fumen(&pages, 0).unwrap();
+        let game = mode.new_game();
+        let piece = game.piece.as_ref().unwrap();
+        assert_eq!(piece.index, tetrominos::TT);
+        assert_eq!(piece.cells(), pages[0].piece.as_ref().unwrap().cells());
+        assert_eq!(game.queue.iter().copied().collect::<Vec<_>>(), vec![tetrominos::TI]);
+    }
+
+    #[test]
+    fn fumen_modes_are_named_by_diagram() {
+        let empty = Mode::from_fumen(&fumen::decode("v115@vhAAgH").unwrap(), 0).unwrap();
+        let t = Mode::from_fumen(&fumen::decode("v115@vhAVQJ").unwrap(), 0).unwrap();
+        assert!(empty.name().starts_with("Fumen "));
+        assert_ne!(empty.name(), t.name());
+    }
+
+    #[test]
+    fn dig_races_have_garbage() {
+        let mode = Mode::dig(0, 0.2, Some(1));
+        assert_eq!(mode.name(), "Dig race 1, messiness 0.2, seed 1");
+        assert_eq!(mode.new_game().garbage_rows(), 1);
+    }
+
+    #[test]
+    fn dig_races_are_named_by_their_garbage() {
+        assert_eq!(Mode::dig(10, 1.5, None).name(), "Dig race 10, messiness 1");
+        assert_ne!(Mode::dig(10, 0.0, Some(3)).name(), Mode::dig(10, 0.0, Some(4)).name());
+        assert_ne!(Mode::dig(10, 0.0, None).name(), Mode::dig(10, 1.0, None).name());
+        match Mode::dig(10, f32::NAN, None) {
+            Mode::Dig { messiness, .. } => assert_eq!(messiness, DIG_MESSINESS),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn a_piece_with_nowhere_to_go_can_go_anywhere() {
+        let mode = mode_named("DT cannon");
+        let mut game = Game::with_setup([0; PLAYFIELD_SIZE as usize], Some(&[tetrominos::TT, tetrominos::TT]));
+        assert!(game.spawn());
+        mode.on_spawn(&mut game);
+        assert_eq!(game.target, Some(vec![]));
+
+        // Swapping it for the other T doesn't help
+        assert!(game.hold());
+        mode.on_spawn(&mut game);
+        assert_eq!(game.target, None);
+    }
+
+    fn mode_named(name: &str) -> Mode {
+        load_modes().into_iter().find(|mode| mode.name() == name).unwrap()
+    }
+}
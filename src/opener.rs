@@ -0,0 +1,170 @@
+use serde::Deserialize;
+use log::warn;
+use crate::Result;
+use crate::game::{Playfield, PLAYFIELD_COLS, PLAYFIELD_ROWS};
+use crate::puzzle;
+use crate::tetrominos::Tetromino;
+
+const BUILTIN_OPENERS: [&str; 4] = [
+    include_str!("../openers/tki.toml"),
+    include_str!("../openers/pco.toml"),
+    include_str!("../openers/mko.toml"),
+    include_str!("../openers/dt_cannon.toml"),
+];
+
+/// A standard opening setup, built up in stages of one bag each
+#[derive(Clone)]
+pub struct Opener {
+    pub name: String,
+    pub description: String,
+    /// The board to build by the end of each stage, every stage including the ones before
+    pub stages: Vec<Playfield>,
+}
+
+/// On-disk representation of an opener
+#[derive(Deserialize)]
+struct OpenerFile {
+    name: String,
+    #[serde(default)]
+    description: String,
+    stages: Vec<String>,
+}
+
+/// Parses an opener from TOML. Stages are drawn like puzzle fields, with the letter of
+/// the piece that goes on each cell.
+pub fn parse_opener(src: &str) -> Result<Opener> {
+    let file: OpenerFile = toml::from_str(src)?;
+    let stages = file.stages.iter()
+        .map(|stage| puzzle::parse_field(stage))
+        .collect::<Result<Vec<Playfield>>>()?;
+    if stages.is_empty() {
+        return Err(format!("opener {:?} has no stages", file.name).into());
+    }
+    for (i, pair) in stages.windows(2).enumerate() {
+        if pair[0].iter().zip(pair[1].iter()).any(|(&before, &after)| before != 0 && after != before) {
+            return Err(format!("stage {} of {:?} doesn't keep the blocks of stage {}", i + 2, file.name, i + 1).into());
+        }
+    }
+    Ok(Opener {
+        name: file.name,
+        description: file.description,
+        stages,
+    })
+}
+
+impl Opener {
+    /// The first stage that hasn't been built yet, or `None` once the opener is complete
+    pub fn stage(&self, playfield: &Playfield) -> Option<usize> {
+        self.stages.iter().position(|stage| {
+            stage.iter().zip(playfield.iter()).any(|(&target, &block)| target != 0 && block == 0)
+        })
+    }
+
+    /// Where the next piece of type `index` should go: the first unbuilt piece of that type
+    /// in the current stage, or else in a later one
+    pub fn next_placement(&self, playfield: &Playfield, index: Tetromino) -> Option<Vec<[i32; 2]>> {
+        let current = self.stage(playfield)?;
+        self.stages[current..].iter().find_map(|stage| {
+            let open = |i: usize| stage[i] == index && playfield[i] == 0;
+            (0..stage.len())
+                .filter(|&i| open(i))
+                .map(|i| connected(i, &open))
+                .find(|cells| cells.len() == 4)
+        })
+    }
+}
+
+// The cells reachable from `start` through neighbours that are `open`
+fn connected(start: usize, open: &dyn Fn(usize) -> bool) -> Vec<[i32; 2]> {
+    let cols = PLAYFIELD_COLS as i32;
+    let mut cells = vec![[start as i32 % cols, start as i32 / cols]];
+    let mut i = 0;
+    while i < cells.len() {
+        let [c, r] = cells[i];
+        for [nc, nr] in [[c - 1, r], [c + 1, r], [c, r - 1], [c, r + 1]].iter().copied() {
+            let inside = nc >= 0 && nc < cols && nr >= 0 && nr < PLAYFIELD_ROWS as i32;
+            if inside && open((nr * cols + nc) as usize) && !cells.contains(&[nc, nr]) {
+                cells.push([nc, nr]);
+            }
+        }
+        i += 1;
+    }
+    cells
+}
+
+pub fn load_openers() -> Vec<Opener> {
+    BUILTIN_OPENERS.iter()
+        .filter_map(|src| match parse_opener(src) {
+            Ok(opener) => Some(opener),
+            Err(err) => {
+                warn!("Failed to parse built-in opener: {}", err);
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::PLAYFIELD_SIZE;
+    use crate::tetrominos::{self, TT};
+
+    fn dt_cannon() -> Opener {
+        load_openers().into_iter().find(|opener| opener.name == "DT cannon").unwrap()
+    }
+
+    #[test]
+    fn every_builtin_opener_parses() {
+        for src in BUILTIN_OPENERS.iter() {
+            let opener = parse_opener(src).unwrap();
+            for stage in &opener.stages {
+                // Whole pieces only, and the T pieces are kept for the spins
+                for index in 1..tetrominos::GARBAGE {
+                    let blocks = stage.iter().filter(|&&block| block == index).count();
+                    assert_eq!(blocks % 4, 0, "{} has a broken piece", opener.name);
+                    assert!(index != TT || blocks == 0, "{} places a T", opener.name);
+                }
+            }
+        }
+        assert_eq!(load_openers().len(), BUILTIN_OPENERS.len());
+    }
+
+    #[test]
+    fn stages_are_built_in_turn() {
+        let opener = dt_cannon();
+        let mut playfield = [0; PLAYFIELD_SIZE as usize];
+        assert_eq!(opener.stage(&playfield), Some(0));
+        playfield = opener.stages[0];
+        assert_eq!(opener.stage(&playfield), Some(1));
+        playfield = opener.stages[1];
+        assert_eq!(opener.stage(&playfield), None);
+        assert_eq!(opener.next_placement(&playfield, tetrominos::TI), None);
+    }
+
+    #[test]
+    fn next_placement_looks_ahead_to_later_stages() {
+        let opener = dt_cannon();
+        let mut playfield = [0; PLAYFIELD_SIZE as usize];
+        let first = opener.next_placement(&playfield, tetrominos::TI).unwrap();
+        assert!(first.iter().all(|&[c, r]| opener.stages[0][(r * PLAYFIELD_COLS as i32 + c) as usize] != 0));
+
+        // With the I of the first stage in place, the next one goes to the second
+        for [c, r] in first {
+            playfield[(r * PLAYFIELD_COLS as i32 + c) as usize] = tetrominos::TI;
+        }
+        let second = opener.next_placement(&playfield, tetrominos::TI).unwrap();
+        assert!(second.iter().all(|&[c, r]| opener.stages[0][(r * PLAYFIELD_COLS as i32 + c) as usize] == 0));
+        assert_eq!(opener.next_placement(&playfield, TT), None);
+    }
+
+    #[test]
+    fn later_stages_keep_the_earlier_blocks() {
+        let src = r#"
+            name = "Broken"
+            stages = ["..........\nIIII......", "..........\n....IIII.."]
+        "#;
+        assert!(parse_opener(src).is_err());
+        assert!(parse_opener("name = \"Empty\"\nstages = []").is_err());
+    }
+}
@@ -0,0 +1,197 @@
+use serde::{Serialize, Deserialize};
+use std::fs;
+use log::{warn, info};
+use crate::Result;
+use crate::config;
+use crate::rotation::Rotation;
+use crate::game::{Game, LockResult, Playfield, PLAYFIELD_COLS, PLAYFIELD_ROWS, PLAYFIELD_SIZE};
+use crate::tetrominos::{self, Tetromino};
+
+const BUILTIN_PUZZLES: [&str; 3] = [
+    include_str!("../puzzles/tetris.toml"),
+    include_str!("../puzzles/perfect_clear.toml"),
+    include_str!("../puzzles/t_spin_double.toml"),
+];
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Goal {
+    ClearLines(u32),
+    PerfectClear,
+    TSpinDouble,
+}
+
+impl Goal {
+    pub fn is_met(&self, game: &Game, lock: &LockResult) -> bool {
+        match self {
+            Goal::ClearLines(lines) => game.lines >= *lines,
+            Goal::PerfectClear => lock.perfect_clear,
+            Goal::TSpinDouble => lock.t_spin && lock.lines == 2,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Puzzle {
+    pub name: String,
+    pub description: String,
+    pub playfield: Playfield,
+    pub sequence: Vec<Tetromino>,
+    pub goal: Goal,
+    pub rotation: Rotation,
+}
+
+/// On-disk representation of a puzzle
+#[derive(Deserialize)]
+struct PuzzleFile {
+    name: String,
+    #[serde(default)]
+    description: String,
+    sequence: String,
+    goal: Goal,
+    #[serde(default)]
+    rotation: Rotation,
+    #[serde(default)]
+    field: String,
+}
+
+/// Parses a puzzle from TOML. The field is given as rows of ASCII, top to bottom, where
+/// `.` is an empty cell, `X` a garbage block and the tetromino letters are blocks of that
+/// colour. Rows are aligned to the bottom of the playfield.
+pub fn parse_puzzle(src: &str) -> Result<Puzzle> {
+    let file: PuzzleFile = toml::from_str(src)?;
+    Ok(Puzzle {
+        playfield: parse_field(&file.field)?,
+        sequence: parse_sequence(&file.sequence)?,
+        name: file.name,
+        description: file.description,
+        goal: file.goal,
+        rotation: file.rotation,
+    })
+}
+
+pub fn parse_field(src: &str) -> Result<Playfield> {
+    let rows: Vec<&str> = src.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .collect();
+    if rows.len() > PLAYFIELD_ROWS as usize {
+        return Err(format!("field has {} rows, max is {}", rows.len(), PLAYFIELD_ROWS).into());
+    }
+
+    let mut playfield = [0; PLAYFIELD_SIZE as usize];
+    let first_row = PLAYFIELD_ROWS as usize - rows.len();
+    for (r, row) in rows.iter().enumerate() {
+        if row.chars().count() != PLAYFIELD_COLS as usize {
+            return Err(format!("field row {:?} is not {} cells wide", row, PLAYFIELD_COLS).into());
+        }
+        for (c, cell) in row.chars().enumerate() {
+            let block = match cell {
+                '.' | '_' => 0,
+                'X' | '#' => tetrominos::GARBAGE,
+                _ => tetrominos::from_name(cell)
+                    .ok_or_else(|| format!("invalid field cell {:?}", cell))?,
+            };
+            playfield[(first_row + r) * PLAYFIELD_COLS as usize + c] = block;
+        }
+    }
+    Ok(playfield)
+}
+
+pub fn parse_sequence(src: &str) -> Result<Vec<Tetromino>> {
+    src.chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| tetrominos::from_name(c).ok_or_else(|| format!("invalid piece {:?}", c).into()))
+        .collect()
+}
+
+/// Loads the built-in puzzles followed by any *.toml files in the puzzle directory
+pub fn load_puzzles() -> Vec<Puzzle> {
+    let mut puzzles = Vec::new();
+    for src in BUILTIN_PUZZLES.iter() {
+        match parse_puzzle(src) {
+            Ok(puzzle) => puzzles.push(puzzle),
+            Err(err) => warn!("Failed to parse built-in puzzle: {}", err),
+        }
+    }
+
+    let puzzle_dir = match config::get_puzzle_dir() {
+        Ok(dir) if dir.is_dir() => dir,
+        _ => return puzzles,
+    };
+    let mut files: Vec<_> = match fs::read_dir(&puzzle_dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|e| e == "toml"))
+            .collect(),
+        Err(err) => {
+            warn!("Failed to read puzzle directory {:?}: {}", puzzle_dir, err);
+            return puzzles;
+        }
+    };
+    files.sort();
+
+    for file in files {
+        match fs::read_to_string(&file).map_err(|e| e.into()).and_then(|s| parse_puzzle(&s)) {
+            Ok(puzzle) => {
+                info!("Loaded puzzle {:?} from {:?}", puzzle.name, file);
+                puzzles.push(puzzle)
+            },
+            Err(err) => warn!("Failed to load puzzle {:?}: {}", file, err),
+        }
+    }
+    puzzles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tetrominos::{GARBAGE, TI, TO, TT, TZ};
+
+    #[test]
+    fn fields_are_aligned_to_the_bottom() {
+        let field = parse_field("
+            I.........
+            XXXX_XXXXT").unwrap();
+        assert_eq!(field[14 * 10], TI);
+        assert_eq!(field[15 * 10], GARBAGE);
+        assert_eq!(field[15 * 10 + 4], 0);
+        assert_eq!(field[15 * 10 + 9], TT);
+        assert_eq!(field.iter().filter(|&&b| b != 0).count(), 10);
+        assert_eq!(parse_field("").unwrap(), [0; PLAYFIELD_SIZE as usize]);
+    }
+
+    #[test]
+    fn bad_fields_are_rejected() {
+        assert!(parse_field("XXXXXXXXX").is_err());
+        assert!(parse_field("XXXXXXXXXXX").is_err());
+        assert!(parse_field("XXXXXXXXXQ").is_err());
+        let too_tall = vec![".........."; PLAYFIELD_ROWS as usize + 1].join("\n");
+        assert!(parse_field(&too_tall).is_err());
+    }
+
+    #[test]
+    fn sequences_skip_whitespace() {
+        assert_eq!(parse_sequence("IO T\nZ").unwrap(), vec![TI, TO, TT, TZ]);
+        assert_eq!(parse_sequence("").unwrap(), vec![]);
+        assert!(parse_sequence("IOX").is_err());
+    }
+
+    #[test]
+    fn goals_are_met_by_the_right_lock() {
+        let mut game = Game::new();
+        let lock = |lines, t_spin, perfect_clear| LockResult { lines, t_spin, perfect_clear, ..LockResult::default() };
+
+        game.lines = 3;
+        assert!(!Goal::ClearLines(4).is_met(&game, &lock(3, false, false)));
+        game.lines = 4;
+        assert!(Goal::ClearLines(4).is_met(&game, &lock(1, false, false)));
+
+        assert!(Goal::PerfectClear.is_met(&game, &lock(2, false, true)));
+        assert!(!Goal::PerfectClear.is_met(&game, &lock(4, false, false)));
+
+        assert!(Goal::TSpinDouble.is_met(&game, &lock(2, true, false)));
+        assert!(!Goal::TSpinDouble.is_met(&game, &lock(2, false, false)));
+        assert!(!Goal::TSpinDouble.is_met(&game, &lock(3, true, false)));
+    }
+}
@@ -0,0 +1,218 @@
+use std::fs;
+use std::path::Path;
+use crate::Result;
+use crate::game::{FrameInput, Game, PLAYFIELD_SIZE};
+use crate::modes::Mode;
+use crate::scores::GameResult;
+
+/// The input and speed of every frame of a game, which together with its seed is enough
+/// to play it again
+#[derive(Clone, Default)]
+pub struct Replay {
+    frames: Vec<u8>,
+    /// Frames where the speed changed, and the speed from there on
+    speeds: Vec<(usize, f32)>,
+}
+
+impl Replay {
+    pub fn record(&mut self, input: &FrameInput, speed: f32) {
+        if self.speeds.last().map(|&(_, last)| last) != Some(speed) {
+            self.speeds.push((self.frames.len(), speed));
+        }
+        self.frames.push(encode_input(input));
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// The input and speed of frame `index`
+    pub fn frame(&self, index: usize) -> Option<(FrameInput, f32)> {
+        let input = decode_input(*self.frames.get(index)?);
+        let speed = self.speeds.iter().rev()
+            .find(|&&(from, _)| from <= index)
+            .map_or(1.0, |&(_, speed)| speed);
+        Some((input, speed))
+    }
+
+    /// Encodes the replay as text, with runs of the same input written as `input*count`
+    /// and speed changes as `@speed`
+    pub fn encode(&self) -> String {
+        let mut tokens = Vec::new();
+        let mut speeds = self.speeds.iter().peekable();
+        let mut i = 0;
+        while i < self.frames.len() {
+            if let Some((_, speed)) = speeds.next_if(|&&(from, _)| from == i) {
+                tokens.push(format!("@{}", speed));
+            }
+            let next_speed = speeds.peek().map_or(self.frames.len(), |&&(from, _)| from);
+            let run = self.frames[i..next_speed].iter().take_while(|&&f| f == self.frames[i]).count();
+            tokens.push(match run {
+                1 => format!("{}", self.frames[i]),
+                _ => format!("{}*{}", self.frames[i], run),
+            });
+            i += run;
+        }
+        tokens.join(" ")
+    }
+
+    pub fn save(&self, file: &Path) -> Result<()> {
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(file, self.encode())?;
+        Ok(())
+    }
+
+    pub fn load(file: &Path) -> Result<Replay> {
+        Replay::decode(&fs::read_to_string(file)?)
+    }
+
+    pub fn decode(src: &str) -> Result<Replay> {
+        let mut replay = Replay::default();
+        for token in src.split_whitespace() {
+            if let Some(speed) = token.strip_prefix('@') {
+                replay.speeds.push((replay.frames.len(), speed.parse()?));
+                continue;
+            }
+            let (input, count) = match token.split_once('*') {
+                Some((input, count)) => (input.parse::<u8>()?, count.parse::<usize>()?),
+                None => (token.parse::<u8>()?, 1),
+            };
+            if input >= INPUT_CODES {
+                return Err(format!("invalid replay input {}", input).into());
+            }
+            replay.frames.resize(replay.frames.len() + count, input);
+        }
+        Ok(replay)
+    }
+}
+
+// Every combination of the keys of a frame: three ways for the shift, held shift and
+// rotation, two for soft drop, hard drop and hold
+const INPUT_CODES: u8 = 3 * 3 * 3 * 2 * 2 * 2;
+
+fn encode_input(input: &FrameInput) -> u8 {
+    let dir = |d: i8| (d.signum() + 1) as u8;
+    dir(input.shift) + 3 * dir(input.held_shift) + 9 * dir(input.rotate) +
+        27 * input.soft_drop as u8 + 54 * input.hard_drop as u8 + 108 * input.hold as u8
+}
+
+fn decode_input(code: u8) -> FrameInput {
+    let dir = |code: u8| (code % 3) as i8 - 1;
+    FrameInput {
+        shift: dir(code),
+        held_shift: dir(code / 3),
+        rotate: dir(code / 9),
+        soft_drop: (code / 27) % 2 == 1,
+        hard_drop: (code / 54) % 2 == 1,
+        hold: code / 108 == 1,
+    }
+}
+
+/// A finished game played again alongside a new one, frame by frame
+pub struct Ghost {
+    pub game: Game,
+    replay: Replay,
+    frame: usize,
+}
+
+impl Ghost {
+    /// The ghost of `result`, if it has a replay. Only works for modes that start on an
+    /// empty board with random pieces, where the seed decides everything else.
+    pub fn new(mode: &Mode, result: &GameResult) -> Option<Ghost> {
+        let replay = Replay::load(result.replay_file.as_ref()?).ok()?;
+        let mut game = Game::with_seed([0; PLAYFIELD_SIZE as usize], None, result.seed);
+        game.set_rules(mode.rules());
+        Some(Ghost { game, replay, frame: 0 })
+    }
+
+    /// Plays the next frame, until the replay runs out
+    pub fn step(&mut self) {
+        if let Some((input, speed)) = self.replay.frame(self.frame) {
+            self.game.speed = speed;
+            self.game.step(&input);
+            self.frame += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(code: u8) -> FrameInput {
+        decode_input(code)
+    }
+
+    #[test]
+    fn every_input_round_trips() {
+        for code in 0..INPUT_CODES {
+            assert_eq!(encode_input(&decode_input(code)), code);
+        }
+        assert_eq!(encode_input(&FrameInput::default()), 13);
+    }
+
+    #[test]
+    fn encodes_runs_and_speed_changes() {
+        let mut replay = Replay::default();
+        for _ in 0..3 {
+            replay.record(&input(13), 1.0);
+        }
+        replay.record(&input(14), 1.0);
+        replay.record(&input(14), 2.5);
+        replay.record(&input(68), 2.5);
+        assert_eq!(replay.encode(), "@1 13*3 14 @2.5 14 68");
+
+        let decoded = Replay::decode(&replay.encode()).unwrap();
+        assert_eq!(decoded.len(), 6);
+        assert_eq!(decoded.encode(), replay.encode());
+        let (frame, speed) = decoded.frame(5).unwrap();
+        assert!(frame.hard_drop && frame.shift == 1);
+        assert_eq!(speed, 2.5);
+        assert_eq!(decoded.frame(2).unwrap().1, 1.0);
+        assert!(decoded.frame(6).is_none());
+    }
+
+    #[test]
+    fn rejects_bad_replays() {
+        assert!(Replay::decode("13 x").is_err());
+        assert!(Replay::decode("216").is_err());
+        assert!(Replay::decode("13*y").is_err());
+        assert!(Replay::decode("@fast 13").is_err());
+        assert_eq!(Replay::decode("").unwrap().len(), 0);
+    }
+
+    #[test]
+    fn plays_back_the_same_game() {
+        let seed = 7;
+        let mut game = Game::with_seed([0; PLAYFIELD_SIZE as usize], None, seed);
+        let mut replay = Replay::default();
+        for frame in 0..600 {
+            let input = FrameInput {
+                // Spreads the pieces over the left, middle and right of the board
+                shift: if frame % 2 == 0 { [-1, 0, 1][frame / 20 % 3] } else { 0 },
+                rotate: if frame % 7 == 0 { 1 } else { 0 },
+                hard_drop: frame % 20 == 19,
+                ..FrameInput::default()
+            };
+            replay.record(&input, 1.0);
+            game.step(&input);
+        }
+
+        let replay = Replay::decode(&replay.encode()).unwrap();
+        let mut ghost = Game::with_seed([0; PLAYFIELD_SIZE as usize], None, seed);
+        for frame in 0..replay.len() {
+            let (input, speed) = replay.frame(frame).unwrap();
+            ghost.speed = speed;
+            ghost.step(&input);
+        }
+        assert!(game.stats.pieces > 10);
+        assert_eq!(ghost.stats.pieces, game.stats.pieces);
+        assert_eq!(ghost.playfield[..], game.playfield[..]);
+    }
+}
@@ -0,0 +1,389 @@
+use serde::{Serialize, Deserialize};
+use crate::game::{is_free, Piece, Playfield};
+use crate::tetrominos::{self, Tetromino, TetroShape};
+
+/// Decides the shape of every orientation of a piece, and where a rotated piece may be
+/// moved to ("kicked") when the rotated shape doesn't fit where it is.
+/// Orientations go from 0 (spawn) clockwise through 1, 2 and 3.
+pub trait RotationSystem: Sync {
+    fn name(&self) -> &'static str;
+
+    fn shape(&self, index: Tetromino, rot: u8) -> TetroShape;
+
+    /// Offsets to try, in order, when rotating `piece` to orientation `rot`.
+    /// The first offset where the rotated piece fits is used.
+    fn kicks(&self, piece: &Piece, rot: u8, playfield: &Playfield) -> Vec<[i32; 2]>;
+
+    fn spawn_orientation(&self, _index: Tetromino) -> u8 {
+        0
+    }
+
+    /// Spawns at column 3, with the top of the piece at the top row
+    fn spawn_position(&self, index: Tetromino) -> [i32; 2] {
+        let shape = self.shape(index, self.spawn_orientation(index));
+        let top = (0..4).find(|&r| (0..4).any(|c| shape.is_solid(c, r))).unwrap_or(0);
+        [3, -(top as i32)]
+    }
+}
+
+/// The rotation systems that can be chosen, e.g. in puzzle files
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Rotation {
+    Matrix,
+    #[default]
+    Srs,
+    Ars,
+    Nes,
+    Sega,
+}
+
+impl Rotation {
+    pub fn system(self) -> &'static dyn RotationSystem {
+        match self {
+            Rotation::Matrix => &Matrix,
+            Rotation::Srs => &Srs,
+            Rotation::Ars => &Ars,
+            Rotation::Nes => &Nes,
+            Rotation::Sega => &Sega,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Rotation> {
+        match name.to_lowercase().as_str() {
+            "matrix" => Some(Rotation::Matrix),
+            "srs" => Some(Rotation::Srs),
+            "ars" => Some(Rotation::Ars),
+            "nes" | "classic" => Some(Rotation::Nes),
+            "sega" => Some(Rotation::Sega),
+            _ => None,
+        }
+    }
+}
+
+/// The original rotation of the tetromino matrices within their box, without any kicks
+pub struct Matrix;
+
+impl RotationSystem for Matrix {
+    fn name(&self) -> &'static str {
+        "Matrix"
+    }
+
+    fn shape(&self, index: Tetromino, rot: u8) -> TetroShape {
+        tetrominos::ALL[index].rotated(rot)
+    }
+
+    fn kicks(&self, _piece: &Piece, _rot: u8, _playfield: &Playfield) -> Vec<[i32; 2]> {
+        vec![[0, 0]]
+    }
+}
+
+/// Super Rotation System, as used by the guideline games
+pub struct Srs;
+
+// Clockwise kicks from orientation 0, 1, 2 and 3, with y pointing down.
+// Counter clockwise kicks are the negated kicks of the opposite clockwise turn.
+const SRS_KICKS: [[[i32; 2]; 5]; 4] = [
+    [[0, 0], [-1, 0], [-1, -1], [0, 2], [-1, 2]],
+    [[0, 0], [1, 0], [1, 1], [0, -2], [1, -2]],
+    [[0, 0], [1, 0], [1, -1], [0, 2], [1, 2]],
+    [[0, 0], [-1, 0], [-1, 1], [0, -2], [-1, -2]],
+];
+
+const SRS_I_KICKS: [[[i32; 2]; 5]; 4] = [
+    [[0, 0], [-2, 0], [1, 0], [-2, 1], [1, -2]],
+    [[0, 0], [-1, 0], [2, 0], [-1, -2], [2, 1]],
+    [[0, 0], [2, 0], [-1, 0], [2, -1], [-1, 2]],
+    [[0, 0], [1, 0], [-2, 0], [1, 2], [-2, -1]],
+];
+
+impl RotationSystem for Srs {
+    fn name(&self) -> &'static str {
+        "SRS"
+    }
+
+    fn shape(&self, index: Tetromino, rot: u8) -> TetroShape {
+        SHAPES.srs[index].rotated(rot)
+    }
+
+    fn kicks(&self, piece: &Piece, rot: u8, _playfield: &Playfield) -> Vec<[i32; 2]> {
+        let table = match piece.index {
+            tetrominos::TI => &SRS_I_KICKS,
+            tetrominos::TO => return vec![[0, 0]],
+            _ => &SRS_KICKS,
+        };
+        if rot == (piece.rot + 1) % 4 {
+            table[piece.rot as usize].to_vec()
+        } else {
+            table[rot as usize].iter().map(|[x, y]| [-x, -y]).collect()
+        }
+    }
+}
+
+/// Arika Rotation System from the TGM games. Pieces sit at the bottom of their box,
+/// and can kick one column right or left, except for the I.
+pub struct Ars;
+
+impl RotationSystem for Ars {
+    fn name(&self) -> &'static str {
+        "ARS"
+    }
+
+    fn shape(&self, index: Tetromino, rot: u8) -> TetroShape {
+        orientation(&SHAPES.ars[index], rot)
+    }
+
+    fn kicks(&self, piece: &Piece, rot: u8, playfield: &Playfield) -> Vec<[i32; 2]> {
+        match piece.index {
+            tetrominos::TI | tetrominos::TO => vec![[0, 0]],
+            tetrominos::TT | tetrominos::TJ | tetrominos::TL
+                if blocked_in_centre_column(piece, &self.shape(piece.index, rot), playfield) => vec![[0, 0]],
+            _ => vec![[0, 0], [1, 0], [-1, 0]],
+        }
+    }
+}
+
+// The centre column rule of ARS: looking through the rotated shape left to right, top to
+// bottom, if the first block that is obstructed lies in the middle column, the piece doesn't kick
+fn blocked_in_centre_column(piece: &Piece, shape: &TetroShape, playfield: &Playfield) -> bool {
+    let first_blocked = (0..3).flat_map(|r| (0..3).map(move |c| (c, r)))
+        .find(|&(c, r)| {
+            shape.is_solid(c, r) && !is_free(piece.pos[0] + c as i32, piece.pos[1] + r as i32, playfield)
+        });
+    matches!(first_blocked, Some((1, _)))
+}
+
+/// The Nintendo rotation of the NES game: no kicks, and the two orientation pieces
+/// (I, S and Z) stay on the right side of their box
+pub struct Nes;
+
+impl RotationSystem for Nes {
+    fn name(&self) -> &'static str {
+        "NES"
+    }
+
+    fn shape(&self, index: Tetromino, rot: u8) -> TetroShape {
+        orientation(&SHAPES.nes[index], rot)
+    }
+
+    fn kicks(&self, _piece: &Piece, _rot: u8, _playfield: &Playfield) -> Vec<[i32; 2]> {
+        vec![[0, 0]]
+    }
+}
+
+/// The rotation of Sega Tetris, which ARS is based on: same shapes, but no kicks
+pub struct Sega;
+
+impl RotationSystem for Sega {
+    fn name(&self) -> &'static str {
+        "Sega"
+    }
+
+    fn shape(&self, index: Tetromino, rot: u8) -> TetroShape {
+        orientation(&SHAPES.ars[index], rot)
+    }
+
+    fn kicks(&self, _piece: &Piece, _rot: u8, _playfield: &Playfield) -> Vec<[i32; 2]> {
+        vec![[0, 0]]
+    }
+}
+
+// Pieces with fewer than four distinct orientations just repeat them
+fn orientation(shapes: &[TetroShape], rot: u8) -> TetroShape {
+    shapes[rot as usize % shapes.len()].clone()
+}
+
+/// Parses a shape drawn with `#` for blocks, three or four rows of as many columns
+fn parse_shape(rows: &[&str]) -> TetroShape {
+    let solid = |r: usize, c: usize| rows[r].as_bytes()[c] == b'#';
+    match rows.len() {
+        3 => {
+            let mut t = [[false; 3]; 3];
+            for (r, row) in t.iter_mut().enumerate() {
+                for (c, block) in row.iter_mut().enumerate() {
+                    *block = solid(r, c);
+                }
+            }
+            TetroShape::Odd(t)
+        }
+        _ => {
+            let mut t = [[false; 4]; 4];
+            for (r, row) in t.iter_mut().enumerate() {
+                for (c, block) in row.iter_mut().enumerate() {
+                    *block = solid(r, c);
+                }
+            }
+            TetroShape::Even(t)
+        }
+    }
+}
+
+struct Shapes {
+    /// Spawn shapes, the other orientations are rotations of them
+    srs: Vec<TetroShape>,
+    /// Every distinct orientation of each piece
+    ars: Vec<Vec<TetroShape>>,
+    nes: Vec<Vec<TetroShape>>,
+}
+
+lazy_static! {
+    static ref SHAPES: Shapes = {
+        let none = || parse_shape(&["...", "...", "..."]);
+        let o = || parse_shape(&["....", ".##.", ".##.", "...."]);
+        Shapes {
+            srs: vec![
+                none(),
+                parse_shape(&["....", "####", "....", "...."]),
+                o(),
+                parse_shape(&[".#.", "###", "..."]),
+                parse_shape(&[".##", "##.", "..."]),
+                parse_shape(&["##.", ".##", "..."]),
+                parse_shape(&["#..", "###", "..."]),
+                parse_shape(&["..#", "###", "..."]),
+            ],
+            ars: vec![
+                vec![none()],
+                vec![parse_shape(&["....", "####", "....", "...."]),
+                     parse_shape(&["..#.", "..#.", "..#.", "..#."])],
+                vec![o()],
+                vec![parse_shape(&["...", "###", ".#."]),
+                     parse_shape(&[".#.", "##.", ".#."]),
+                     parse_shape(&["...", ".#.", "###"]),
+                     parse_shape(&[".#.", ".##", ".#."])],
+                vec![parse_shape(&["...", ".##", "##."]),
+                     parse_shape(&["#..", "##.", ".#."])],
+                vec![parse_shape(&["...", "##.", ".##"]),
+                     parse_shape(&["..#", ".##", ".#."])],
+                vec![parse_shape(&["...", "###", "..#"]),
+                     parse_shape(&[".#.", ".#.", "##."]),
+                     parse_shape(&["...", "#..", "###"]),
+                     parse_shape(&[".##", ".#.", ".#."])],
+                vec![parse_shape(&["...", "###", "#.."]),
+                     parse_shape(&["##.", ".#.", ".#."]),
+                     parse_shape(&["...", "..#", "###"]),
+                     parse_shape(&[".#.", ".#.", ".##"])],
+            ],
+            nes: vec![
+                vec![none()],
+                vec![parse_shape(&["....", "....", "####", "...."]),
+                     parse_shape(&["..#.", "..#.", "..#.", "..#."])],
+                vec![o()],
+                vec![parse_shape(&["...", "###", ".#."]),
+                     parse_shape(&[".#.", "##.", ".#."]),
+                     parse_shape(&[".#.", "###", "..."]),
+                     parse_shape(&[".#.", ".##", ".#."])],
+                vec![parse_shape(&["...", ".##", "##."]),
+                     parse_shape(&[".#.", ".##", "..#"])],
+                vec![parse_shape(&["...", "##.", ".##"]),
+                     parse_shape(&["..#", ".##", ".#."])],
+                vec![parse_shape(&["...", "###", "..#"]),
+                     parse_shape(&[".#.", ".#.", "##."]),
+                     parse_shape(&["#..", "###", "..."]),
+                     parse_shape(&[".##", ".#.", ".#."])],
+                vec![parse_shape(&["...", "###", "#.."]),
+                     parse_shape(&["##.", ".#.", ".#."]),
+                     parse_shape(&["..#", "###", "..."]),
+                     parse_shape(&[".#.", ".#.", ".##"])],
+            ],
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{PLAYFIELD_COLS, PLAYFIELD_SIZE};
+
+    const ALL: [Rotation; 5] = [Rotation::Matrix, Rotation::Srs, Rotation::Ars, Rotation::Nes, Rotation::Sega];
+
+    fn piece(index: Tetromino, rotation: Rotation, rot: u8, pos: [i32; 2]) -> Piece {
+        let system = rotation.system();
+        Piece { index, pos, rot, matrix: system.shape(index, rot) }
+    }
+
+    fn block(playfield: &mut Playfield, col: i32, row: i32) {
+        playfield[(row * PLAYFIELD_COLS as i32 + col) as usize] = tetrominos::GARBAGE;
+    }
+
+    #[test]
+    fn every_orientation_has_four_blocks() {
+        for &rotation in &ALL {
+            for index in tetrominos::TI..=tetrominos::TL {
+                for rot in 0..4 {
+                    let cells = piece(index, rotation, rot, [0, 0]).cells();
+                    assert_eq!(cells.len(), 4, "{:?} piece {} orientation {}", rotation, index, rot);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pieces_spawn_on_the_top_row() {
+        for &rotation in &ALL {
+            for index in tetrominos::TI..=tetrominos::TL {
+                let top = Piece::new(index, rotation.system()).cells().iter().map(|[_, r]| *r).min();
+                assert_eq!(top, Some(0), "{:?} piece {}", rotation, index);
+            }
+        }
+    }
+
+    #[test]
+    fn srs_kicks_match_the_guideline() {
+        let playfield = [0; PLAYFIELD_SIZE as usize];
+        let t = piece(tetrominos::TT, Rotation::Srs, 0, [3, 5]);
+        // 0 -> R, with y pointing down
+        assert_eq!(Srs.kicks(&t, 1, &playfield), vec![[0, 0], [-1, 0], [-1, -1], [0, 2], [-1, 2]]);
+        let i = piece(tetrominos::TI, Rotation::Srs, 0, [3, 5]);
+        assert_eq!(Srs.kicks(&i, 1, &playfield), vec![[0, 0], [-2, 0], [1, 0], [-2, 1], [1, -2]]);
+        let o = piece(tetrominos::TO, Rotation::Srs, 0, [3, 5]);
+        assert_eq!(Srs.kicks(&o, 1, &playfield), vec![[0, 0]]);
+    }
+
+    #[test]
+    fn srs_counter_clockwise_kicks_undo_clockwise_ones() {
+        let playfield = [0; PLAYFIELD_SIZE as usize];
+        for &index in &[tetrominos::TT, tetrominos::TI] {
+            for rot in 0..4u8 {
+                let cw = Srs.kicks(&piece(index, Rotation::Srs, rot, [3, 5]), (rot + 1) % 4, &playfield);
+                let ccw = Srs.kicks(&piece(index, Rotation::Srs, (rot + 1) % 4, [3, 5]), rot, &playfield);
+                let undone: Vec<[i32; 2]> = cw.iter().map(|[x, y]| [-x, -y]).collect();
+                assert_eq!(ccw, undone, "piece {} orientation {}", index, rot);
+            }
+        }
+    }
+
+    #[test]
+    fn ars_follows_the_centre_column_rule() {
+        let t = piece(tetrominos::TT, Rotation::Ars, 0, [3, 5]);
+        let mut playfield = [0; PLAYFIELD_SIZE as usize];
+        assert_eq!(Ars.kicks(&t, 1, &playfield), vec![[0, 0], [1, 0], [-1, 0]]);
+
+        // Blocked first in the centre column of the rotated shape: no kicks
+        block(&mut playfield, 4, 5);
+        assert_eq!(Ars.kicks(&t, 1, &playfield), vec![[0, 0]]);
+
+        // Blocked first to the side: kicks as usual
+        let mut playfield = [0; PLAYFIELD_SIZE as usize];
+        block(&mut playfield, 3, 6);
+        assert_eq!(Ars.kicks(&t, 1, &playfield), vec![[0, 0], [1, 0], [-1, 0]]);
+
+        let i = piece(tetrominos::TI, Rotation::Ars, 0, [3, 5]);
+        assert_eq!(Ars.kicks(&i, 1, &playfield), vec![[0, 0]]);
+    }
+
+    #[test]
+    fn nes_and_sega_never_kick() {
+        let playfield = [0; PLAYFIELD_SIZE as usize];
+        for &rotation in &[Rotation::Nes, Rotation::Sega] {
+            let t = piece(tetrominos::TT, rotation, 0, [3, 5]);
+            assert_eq!(rotation.system().kicks(&t, 1, &playfield), vec![[0, 0]]);
+        }
+    }
+
+    #[test]
+    fn names_parse() {
+        assert_eq!(Rotation::from_name("SRS"), Some(Rotation::Srs));
+        assert_eq!(Rotation::from_name("classic"), Some(Rotation::Nes));
+        assert_eq!(Rotation::from_name("tgm"), None);
+    }
+}
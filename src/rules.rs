@@ -0,0 +1,410 @@
+use serde::{Serialize, Deserialize};
+use crate::game::{LockResult, PLAYFIELD_ROWS};
+use crate::rotation::Rotation;
+
+/// Frame rate of the NES, which its timings are counted in
+pub const NES_FPS: f32 = 60.0988;
+
+/// Highest level of the master mode
+pub const MASTER_MAX_LEVEL: u32 = 999;
+
+/// How the next pieces are picked
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Randomizer {
+    /// Every piece is equally likely
+    Uniform,
+    /// Picks from the seven pieces and a "reroll" slot, and picks again, just once,
+    /// if that gave the reroll slot or the same piece as last time
+    NesReroll,
+    /// Deals all seven pieces in a random order before starting over
+    Bag,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Gravity {
+    /// Follows the speed chosen with +/-
+    Speed,
+    /// Frames per row from the NES level table
+    NesFrames,
+    /// Fractions of a row per frame from the TGM level table, up to 20G
+    Tgm,
+}
+
+/// How fast a piece falls
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(tag = "type", content = "value")]
+pub enum GravityRate {
+    FramesPerRow(u32),
+    /// Rows per frame in 1/256ths, 256 being 1G
+    G256(u32),
+}
+
+impl GravityRate {
+    pub fn rows_per_frame(self) -> f32 {
+        match self {
+            GravityRate::FramesPerRow(frames) => 1.0 / frames.max(1) as f32,
+            GravityRate::G256(g) => g as f32 / 256.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Scoring {
+    Guideline,
+    /// 40, 100, 300 or 1200 times the level plus one
+    Nes,
+    /// Based on level, soft dropping, combos and perfect clears ("bravo")
+    Tgm,
+}
+
+/// Frames to wait for the next piece after a lock (ARE)
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(tag = "type", content = "value")]
+pub enum EntryDelay {
+    Frames(u32),
+    /// 10 to 18 frames, longer the higher up the piece locked
+    NesLockHeight,
+    /// Depends on the level section, see `master_timings`
+    Master,
+}
+
+/// Timings of the master mode, in frames, that change as the level goes up
+#[derive(Clone, Copy, Debug)]
+pub struct MasterTimings {
+    pub are: u32,
+    pub line_are: u32,
+    pub das: u32,
+    pub lock_delay: u32,
+    pub line_clear: u32,
+}
+
+/// Everything that differs between the rulesets a mode can be played with.
+/// All timings are counted in frames of `fps`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Rules {
+    pub fps: f32,
+    pub rotation: Rotation,
+    pub hold: bool,
+    pub hard_drop: bool,
+    pub ghost: bool,
+    /// Number of upcoming pieces shown
+    pub previews: usize,
+    pub randomizer: Randomizer,
+    pub gravity: Gravity,
+    pub soft_drop: GravityRate,
+    /// Soft dropping onto the stack locks the piece right away
+    pub soft_drop_lock: bool,
+    pub scoring: Scoring,
+    /// Delayed auto shift as (delay, repeat), or `None` to go with the key repeat of the keyboard
+    pub das: Option<(u32, u32)>,
+    pub entry_delay: EntryDelay,
+    /// Extra wait for the next piece after clearing lines
+    pub line_clear_delay: u32,
+    /// Frames a piece may rest on the stack before locking. Without lock delay it locks
+    /// as soon as it fails to fall.
+    pub lock_delay: Option<u32>,
+    /// Moving or rotating restarts the lock delay, up to this many times. Otherwise only
+    /// falling to a new row does.
+    pub lock_resets: Option<u32>,
+    pub start_level: u32,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules::guideline(Rotation::Srs)
+    }
+}
+
+impl Rules {
+    pub fn guideline(rotation: Rotation) -> Self {
+        Rules {
+            fps: 60.0,
+            rotation,
+            hold: true,
+            hard_drop: true,
+            ghost: true,
+            previews: 5,
+            randomizer: Randomizer::Uniform,
+            gravity: Gravity::Speed,
+            soft_drop: GravityRate::FramesPerRow(2),
+            soft_drop_lock: false,
+            scoring: Scoring::Guideline,
+            das: None,
+            entry_delay: EntryDelay::Frames(0),
+            line_clear_delay: 0,
+            lock_delay: Some(30),
+            lock_resets: Some(15),
+            start_level: 1,
+        }
+    }
+
+    pub fn nes(start_level: u32) -> Self {
+        Rules {
+            fps: NES_FPS,
+            rotation: Rotation::Nes,
+            hold: false,
+            hard_drop: false,
+            ghost: false,
+            previews: 1,
+            randomizer: Randomizer::NesReroll,
+            gravity: Gravity::NesFrames,
+            soft_drop: GravityRate::FramesPerRow(2),
+            soft_drop_lock: true,
+            scoring: Scoring::Nes,
+            das: Some((16, 6)),
+            entry_delay: EntryDelay::NesLockHeight,
+            line_clear_delay: 17,
+            lock_delay: None,
+            lock_resets: None,
+            start_level,
+        }
+    }
+
+    /// Master mode of TGM: ARS, 20G from level 500 and timings that tighten every section
+    pub fn master() -> Self {
+        let timings = master_timings(0);
+        Rules {
+            fps: 60.0,
+            rotation: Rotation::Ars,
+            hold: false,
+            hard_drop: true,
+            ghost: false,
+            previews: 1,
+            randomizer: Randomizer::Uniform,
+            gravity: Gravity::Tgm,
+            soft_drop: GravityRate::G256(256),
+            soft_drop_lock: true,
+            scoring: Scoring::Tgm,
+            das: Some((timings.das, 1)),
+            entry_delay: EntryDelay::Master,
+            line_clear_delay: timings.line_clear,
+            lock_delay: Some(timings.lock_delay),
+            lock_resets: None,
+            start_level: 0,
+        }
+    }
+
+    /// Applies the timings that depend on the level, when the level section changes
+    pub fn update_level(&mut self, level: u32) {
+        if self.entry_delay == EntryDelay::Master {
+            let timings = master_timings(level);
+            self.das = Some((timings.das, 1));
+            self.line_clear_delay = timings.line_clear;
+            self.lock_delay = Some(timings.lock_delay);
+        }
+    }
+
+    pub fn gravity_rate(&self, level: u32, speed: f32) -> GravityRate {
+        match self.gravity {
+            Gravity::Speed => GravityRate::FramesPerRow((120.0 / speed).round().max(1.0) as u32),
+            Gravity::NesFrames => GravityRate::FramesPerRow(nes_frames_per_row(level)),
+            Gravity::Tgm => GravityRate::G256(tgm_gravity(level)),
+        }
+    }
+
+    /// Frames before the next piece appears after locking a piece with its lowest block
+    /// on `row`, clearing `lines`
+    pub fn entry_delay_frames(&self, row: i32, lines: u32, level: u32) -> u32 {
+        let line_clear = if lines > 0 { self.line_clear_delay } else { 0 };
+        let are = match self.entry_delay {
+            EntryDelay::Frames(frames) => frames,
+            EntryDelay::NesLockHeight => {
+                let height = (PLAYFIELD_ROWS as i32 - 1 - row).max(0) as u32;
+                (10 + 2 * ((height + 2) / 4)).min(18)
+            }
+            EntryDelay::Master if lines > 0 => master_timings(level).line_are,
+            EntryDelay::Master => master_timings(level).are,
+        };
+        are + line_clear
+    }
+
+    /// Points for clearing lines at `level`. `combo` is only used by TGM scoring.
+    pub fn score(&self, lock: &LockResult, level: u32, combo: u32) -> u32 {
+        match self.scoring {
+            Scoring::Guideline => lock.score(),
+            Scoring::Nes => [0, 40, 100, 300, 1200][lock.lines.min(4) as usize] * (level + 1),
+            Scoring::Tgm if lock.lines == 0 => 0,
+            Scoring::Tgm => {
+                let bravo = if lock.perfect_clear { 4 } else { 1 };
+                ((level + lock.lines).div_ceil(4) + lock.soft_rows) * lock.lines * combo * bravo
+            }
+        }
+    }
+
+    /// The level after spawning a piece (`lines` is 0) or clearing `lines`,
+    /// `total_lines` being all lines cleared so far
+    pub fn next_level(&self, level: u32, lines: u32, total_lines: u32) -> u32 {
+        match self.scoring {
+            Scoring::Guideline => level,
+            Scoring::Nes => {
+                // The first level up takes longer when starting on a higher level
+                let start = self.start_level;
+                let first = (start * 10 + 10).min(100.max((start * 10).saturating_sub(50)));
+                if total_lines < first {
+                    level
+                } else {
+                    level.max(start + 1 + (total_lines - first) / 10)
+                }
+            }
+            // Pieces stop at the end of every section, only clearing lines gets past it
+            Scoring::Tgm if lines == 0 && (level % 100 == 99 || level >= MASTER_MAX_LEVEL - 1) => level,
+            Scoring::Tgm if lines == 0 => level + 1,
+            Scoring::Tgm => (level + lines).min(MASTER_MAX_LEVEL),
+        }
+    }
+}
+
+fn nes_frames_per_row(level: u32) -> u32 {
+    match level {
+        0..=8 => 48 - 5 * level,
+        9 => 6,
+        10..=12 => 5,
+        13..=15 => 4,
+        16..=18 => 3,
+        19..=28 => 2,
+        _ => 1,
+    }
+}
+
+// (level, gravity in 1/256 G) the gravity changes at
+const TGM_GRAVITY: [(u32, u32); 30] = [
+    (0, 4), (30, 6), (35, 8), (40, 10), (50, 12), (60, 16), (70, 32), (80, 48), (90, 64),
+    (100, 80), (120, 96), (140, 112), (160, 128), (170, 144), (200, 4), (220, 32), (230, 64),
+    (233, 96), (236, 128), (239, 160), (243, 192), (247, 224), (251, 256), (300, 512),
+    (330, 768), (360, 1024), (400, 1280), (420, 1024), (450, 768),
+    // 20G: pieces drop to the bottom the moment they appear
+    (500, 20 * 256),
+];
+
+fn tgm_gravity(level: u32) -> u32 {
+    TGM_GRAVITY.iter()
+        .take_while(|(from, _)| *from <= level)
+        .last()
+        .map_or(4, |(_, gravity)| *gravity)
+}
+
+/// The timings of each section of the master mode
+pub fn master_timings(level: u32) -> MasterTimings {
+    let (are, line_are, das, lock_delay, line_clear) = match level {
+        0..=499 => (25, 25, 14, 30, 40),
+        500..=599 => (25, 25, 8, 30, 25),
+        600..=699 => (25, 16, 8, 30, 16),
+        700..=799 => (16, 12, 8, 30, 12),
+        800..=899 => (12, 6, 8, 30, 6),
+        _ => (12, 6, 6, 17, 6),
+    };
+    MasterTimings { are, line_are, das, lock_delay, line_clear }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clear(lines: u32) -> LockResult {
+        LockResult { lines, ..LockResult::default() }
+    }
+
+    #[test]
+    fn nes_gravity_follows_the_frame_table() {
+        let rules = Rules::nes(0);
+        let frames = |level| rules.gravity_rate(level, 1.0);
+        assert_eq!(frames(0), GravityRate::FramesPerRow(48));
+        assert_eq!(frames(8), GravityRate::FramesPerRow(8));
+        assert_eq!(frames(9), GravityRate::FramesPerRow(6));
+        assert_eq!(frames(18), GravityRate::FramesPerRow(3));
+        assert_eq!(frames(19), GravityRate::FramesPerRow(2));
+        assert_eq!(frames(29), GravityRate::FramesPerRow(1));
+        assert_eq!(frames(40), GravityRate::FramesPerRow(1));
+    }
+
+    #[test]
+    fn nes_scores_by_level() {
+        let rules = Rules::nes(0);
+        assert_eq!(rules.score(&clear(0), 5, 1), 0);
+        assert_eq!(rules.score(&clear(1), 0, 1), 40);
+        assert_eq!(rules.score(&clear(2), 1, 1), 200);
+        assert_eq!(rules.score(&clear(3), 0, 1), 300);
+        assert_eq!(rules.score(&clear(4), 9, 1), 12000);
+    }
+
+    #[test]
+    fn nes_first_level_up_depends_on_the_start_level() {
+        // (start level, lines for the first level up)
+        for &(start, first) in &[(0, 10), (5, 60), (9, 100), (15, 100), (18, 130), (19, 140)] {
+            let rules = Rules::nes(start);
+            assert_eq!(rules.next_level(start, 1, first - 1), start, "start {}", start);
+            assert_eq!(rules.next_level(start, 1, first), start + 1, "start {}", start);
+            assert_eq!(rules.next_level(start + 1, 1, first + 10), start + 2, "start {}", start);
+        }
+    }
+
+    #[test]
+    fn nes_entry_delay_depends_on_lock_height() {
+        let rules = Rules::nes(0);
+        let bottom = PLAYFIELD_ROWS as i32 - 1;
+        assert_eq!(rules.entry_delay_frames(bottom, 0, 0), 10);
+        assert_eq!(rules.entry_delay_frames(bottom - 1, 0, 0), 10);
+        assert_eq!(rules.entry_delay_frames(bottom - 2, 0, 0), 12);
+        assert_eq!(rules.entry_delay_frames(0, 0, 0), 18);
+        assert_eq!(rules.entry_delay_frames(bottom, 4, 0), 10 + 17);
+    }
+
+    #[test]
+    fn guideline_gravity_follows_the_speed() {
+        let rules = Rules::default();
+        assert_eq!(rules.gravity_rate(1, 1.0), GravityRate::FramesPerRow(120));
+        assert_eq!(rules.gravity_rate(1, 1000.0), GravityRate::FramesPerRow(1));
+        assert_eq!(GravityRate::FramesPerRow(4).rows_per_frame(), 0.25);
+        assert_eq!(GravityRate::G256(512).rows_per_frame(), 2.0);
+        assert_eq!(rules.next_level(3, 4, 100), 3);
+    }
+
+    #[test]
+    fn tgm_gravity_reaches_20g_at_500() {
+        let rules = Rules::master();
+        assert_eq!(rules.gravity_rate(0, 1.0), GravityRate::G256(4));
+        assert_eq!(rules.gravity_rate(199, 1.0), GravityRate::G256(144));
+        // Slows down again after 200
+        assert_eq!(rules.gravity_rate(200, 1.0), GravityRate::G256(4));
+        assert_eq!(rules.gravity_rate(251, 1.0), GravityRate::G256(256));
+        assert_eq!(rules.gravity_rate(500, 1.0), GravityRate::G256(20 * 256));
+        assert_eq!(rules.gravity_rate(999, 1.0), GravityRate::G256(20 * 256));
+    }
+
+    #[test]
+    fn tgm_levels_stop_at_the_end_of_sections() {
+        let rules = Rules::master();
+        assert_eq!(rules.next_level(0, 0, 0), 1);
+        assert_eq!(rules.next_level(99, 0, 0), 99);
+        assert_eq!(rules.next_level(99, 1, 1), 100);
+        assert_eq!(rules.next_level(998, 0, 0), 998);
+        assert_eq!(rules.next_level(997, 4, 100), MASTER_MAX_LEVEL);
+    }
+
+    #[test]
+    fn tgm_scores_level_soft_drop_combo_and_bravo() {
+        let rules = Rules::master();
+        assert_eq!(rules.score(&clear(0), 100, 1), 0);
+        assert_eq!(rules.score(&clear(1), 0, 1), 1);
+        assert_eq!(rules.score(&clear(4), 100, 1), 26 * 4);
+        assert_eq!(rules.score(&LockResult { soft_rows: 6, ..clear(4) }, 100, 2), 32 * 4 * 2);
+        assert_eq!(rules.score(&LockResult { perfect_clear: true, ..clear(4) }, 100, 1), 26 * 4 * 4);
+    }
+
+    #[test]
+    fn master_timings_tighten_by_section() {
+        let mut rules = Rules::master();
+        assert_eq!(rules.das, Some((14, 1)));
+        assert_eq!(rules.entry_delay_frames(0, 0, 0), 25);
+        assert_eq!(rules.entry_delay_frames(0, 1, 0), 25 + 40);
+
+        rules.update_level(700);
+        assert_eq!(rules.das, Some((8, 1)));
+        assert_eq!(rules.entry_delay_frames(0, 0, 700), 16);
+        assert_eq!(rules.entry_delay_frames(0, 1, 700), 12 + 12);
+
+        rules.update_level(900);
+        assert_eq!(rules.lock_delay, Some(17));
+        assert_eq!(rules.das, Some((6, 1)));
+    }
+}
@@ -0,0 +1,170 @@
+use serde::{Serialize, Deserialize};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+use crate::Result;
+use crate::config;
+use crate::game::Game;
+
+/// Bump this whenever the game state changes in a way older versions can't read
+const SAVE_VERSION: u32 = 1;
+
+/// A game left in progress, to be resumed on the next launch
+#[derive(Serialize, Deserialize)]
+pub struct SavedGame {
+    version: u32,
+    /// Name of the mode the game was played in
+    pub mode: String,
+    pub game: Game,
+}
+
+// Read before the rest, so that a save from another version fails on the version number
+// rather than on whatever field changed
+#[derive(Deserialize)]
+struct SaveHeader {
+    version: u32,
+}
+
+pub fn get_save_file() -> Result<PathBuf> {
+    Ok(config::get_config_file()?.with_extension("save.toml"))
+}
+
+impl SavedGame {
+    pub fn new(mode: &str, game: &Game) -> Self {
+        SavedGame { version: SAVE_VERSION, mode: mode.to_string(), game: game.clone() }
+    }
+
+    pub fn from_toml(src: &str) -> Result<SavedGame> {
+        let header: SaveHeader = toml::from_str(src)?;
+        if header.version != SAVE_VERSION {
+            return Err(format!("saved game has version {}, this version of ruzzle can only resume version {}",
+                               header.version, SAVE_VERSION).into());
+        }
+        let mut saved: SavedGame = toml::from_str(src)?;
+        saved.game.restore();
+        Ok(saved)
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        // Going through a `Value` puts the plain values of every table before the nested
+        // tables, which TOML requires
+        Ok(toml::to_string(&toml::Value::try_from(self)?)?)
+    }
+
+    /// Loads the saved game, if there is one
+    pub fn load() -> Result<Option<SavedGame>> {
+        let save_file = get_save_file()?;
+        if !save_file.exists() {
+            return Ok(None);
+        }
+        SavedGame::from_toml(&fs::read_to_string(&save_file)?).map(Some)
+    }
+
+    /// Writes the game to a temporary file first, like the score table
+    pub fn save(&self) -> Result<()> {
+        let save_file = get_save_file()?;
+        let tmp_file = save_file.with_extension("toml.tmp");
+        let contents = self.to_toml()?;
+        {
+            let mut file = File::create(&tmp_file)?;
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp_file, &save_file)?;
+        Ok(())
+    }
+
+    /// Removes the saved game once it has been resumed or replaced by a new game
+    pub fn remove() -> Result<()> {
+        let save_file = get_save_file()?;
+        if save_file.exists() {
+            fs::remove_file(&save_file)?;
+        }
+        Ok(())
+    }
+}
+
+/// Saves a playfield as a list of blocks, serde only handles arrays of up to 32 items
+pub(crate) mod playfield {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+    use std::convert::TryInto;
+    use crate::game::{Playfield, PLAYFIELD_SIZE};
+
+    pub fn serialize<S: Serializer>(playfield: &Playfield, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(playfield.iter())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Playfield, D::Error> {
+        let blocks = Vec::<usize>::deserialize(deserializer)?;
+        let len = blocks.len();
+        blocks.try_into().map_err(|_| {
+            D::Error::custom(format!("playfield has {} blocks, expected {}", len, PLAYFIELD_SIZE))
+        })
+    }
+}
+
+/// Saves the random generator as its seed, stream and position in the stream
+pub(crate) mod rng {
+    use serde::{Serialize, Deserialize, Deserializer, Serializer};
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha12Rng;
+
+    #[derive(Serialize, Deserialize)]
+    struct RngState {
+        seed: [u8; 32],
+        stream: u64,
+        // A game won't get anywhere near 2^64 words
+        word_pos: u64,
+    }
+
+    pub fn serialize<S: Serializer>(rng: &ChaCha12Rng, serializer: S) -> Result<S::Ok, S::Error> {
+        RngState {
+            seed: rng.get_seed(),
+            stream: rng.get_stream(),
+            word_pos: rng.get_word_pos() as u64,
+        }.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ChaCha12Rng, D::Error> {
+        let state = RngState::deserialize(deserializer)?;
+        let mut rng = ChaCha12Rng::from_seed(state.seed);
+        rng.set_stream(state.stream);
+        rng.set_word_pos(state.word_pos as u128);
+        Ok(rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_a_game() {
+        let mut game = Game::new();
+        game.spawn();
+        game.lines = 12;
+        game.playfield[150] = 3;
+        let saved = SavedGame::from_toml(&SavedGame::new("Sprint", &game).to_toml().unwrap()).unwrap();
+        assert_eq!(saved.mode, "Sprint");
+        assert_eq!(saved.game.lines, 12);
+        assert_eq!(saved.game.playfield[..], game.playfield[..]);
+        assert_eq!(saved.game.piece.map(|p| p.cells()), game.piece.map(|p| p.cells()));
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let src = SavedGame::new("Sprint", &Game::new()).to_toml().unwrap()
+            .replace(&format!("version = {}", SAVE_VERSION), "version = 999");
+        let err = SavedGame::from_toml(&src).err().unwrap().to_string();
+        assert!(err.contains("version 999"), "{}", err);
+    }
+
+    #[test]
+    fn checks_the_version_before_the_game() {
+        // An old save whose game no longer parses still reports the version
+        let err = SavedGame::from_toml("version = 0\nmode = \"Sprint\"\n[game]\n").err().unwrap().to_string();
+        assert!(err.contains("version 0"), "{}", err);
+        assert!(SavedGame::from_toml("mode = \"Sprint\"").is_err());
+    }
+}
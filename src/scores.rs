@@ -0,0 +1,253 @@
+use serde::{Serialize, Deserialize};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::warn;
+use crate::Result;
+use crate::config;
+
+/// Bump this whenever the file layout changes in a way older versions can't read
+const SCORES_VERSION: u32 = 1;
+/// Results kept per mode, the first being the personal best
+const TABLE_LEN: usize = 10;
+
+/// How results of a mode are ordered
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Ranking {
+    /// Highest score first
+    Score,
+    /// Fastest time first
+    Time,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GameResult {
+    pub mode: String,
+    pub score: u32,
+    pub lines: u32,
+    pub time_secs: f32,
+    /// Seconds since the unix epoch
+    pub date: u64,
+    pub seed: u32,
+    /// The replay of the game, in a file of its own
+    #[serde(default)]
+    pub replay_file: Option<PathBuf>,
+    /// Time at every 10 lines, for modes that race the personal best
+    #[serde(default)]
+    pub splits: Vec<f32>,
+}
+
+impl GameResult {
+    fn is_better(&self, other: &GameResult, ranking: Ranking) -> bool {
+        match ranking {
+            Ranking::Score if self.score != other.score => self.score > other.score,
+            _ => self.time_secs < other.time_secs,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ScoreTable {
+    version: u32,
+    #[serde(default)]
+    results: Vec<GameResult>,
+}
+
+pub fn get_scores_file() -> Result<PathBuf> {
+    Ok(config::get_config_file()?.with_extension("scores.toml"))
+}
+
+/// Directory next to the scores file where the replays of results are kept
+pub fn get_replay_dir() -> Result<PathBuf> {
+    Ok(get_scores_file()?.with_file_name("replays"))
+}
+
+/// A file for the replay of the game played at `date` with `seed`
+pub fn get_replay_file(date: u64, seed: u32) -> Result<PathBuf> {
+    Ok(get_replay_dir()?.join(format!("{}-{}.replay", date, seed)))
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+impl ScoreTable {
+    /// Loads the score table, or an empty one if there is no score file yet
+    pub fn load() -> Result<ScoreTable> {
+        let scores_file = get_scores_file()?;
+        if !scores_file.exists() {
+            return Ok(ScoreTable { version: SCORES_VERSION, results: Vec::new() });
+        }
+        let table: ScoreTable = toml::from_str(&fs::read_to_string(&scores_file)?)?;
+        if table.version != SCORES_VERSION {
+            return Err(format!("unsupported score file version {} (expected {})",
+                               table.version, SCORES_VERSION).into());
+        }
+        Ok(table)
+    }
+
+    /// Writes the table to a temporary file first and then moves it in place, so that a
+    /// crash half way through never leaves a truncated score file behind
+    pub fn save(&self) -> Result<()> {
+        let scores_file = get_scores_file()?;
+        let tmp_file = scores_file.with_extension("toml.tmp");
+        let contents = toml::to_string_pretty(&self)?;
+        {
+            let mut file = File::create(&tmp_file)?;
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp_file, &scores_file)?;
+        Ok(())
+    }
+
+    /// Adds a result and returns its position in the mode's table, if it made it in.
+    /// Results that drop out of the table are removed, along with their replays.
+    pub fn add(&mut self, result: GameResult, ranking: Ranking) -> Option<usize> {
+        let mode = result.mode.clone();
+        self.results.push(result);
+        let added = self.results.len() - 1;
+        let top: Vec<usize> = self.top(&mode, ranking).iter().map(|&(index, _)| index).collect();
+
+        let (kept, dropped): (Vec<_>, Vec<_>) = self.results.drain(..)
+            .enumerate()
+            .partition(|(index, result)| result.mode != mode || top.contains(index));
+        self.results = kept.into_iter().map(|(_, result)| result).collect();
+        for file in dropped.into_iter().filter_map(|(_, result)| result.replay_file) {
+            if let Err(err) = fs::remove_file(&file) {
+                warn!("Failed to remove replay {}: {}", file.display(), err);
+            }
+        }
+        top.iter().position(|&index| index == added)
+    }
+
+    /// The personal best of a mode
+    pub fn best(&self, mode: &str, ranking: Ranking) -> Option<&GameResult> {
+        self.top(mode, ranking).first().map(|&(_, result)| result)
+    }
+
+    /// The best results of a mode, along with their index in the table
+    fn top(&self, mode: &str, ranking: Ranking) -> Vec<(usize, &GameResult)> {
+        let mut results: Vec<(usize, &GameResult)> = self.results.iter()
+            .enumerate()
+            .filter(|(_, r)| r.mode == mode)
+            .collect();
+        // Stable sort, so earlier results win ties
+        results.sort_by(|(_, a), (_, b)| {
+            if a.is_better(b, ranking) {
+                std::cmp::Ordering::Less
+            } else if b.is_better(a, ranking) {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        });
+        results.truncate(TABLE_LEN);
+        results
+    }
+
+    /// Formats the top 10 of a mode, marking the personal best and the `highlight`ed entry
+    pub fn format_top(&self, mode: &str, ranking: Ranking, highlight: Option<usize>) -> String {
+        let mut out = format!(" Top {} - {}\n", TABLE_LEN, mode);
+        out += "      #  Score    Lines  Time       Date        Seed\n";
+        for (pos, (_, result)) in self.top(mode, ranking).iter().enumerate() {
+            let marker = match (pos, highlight) {
+                (0, Some(0)) => "NEW PB",
+                (0, _) => "PB",
+                (p, Some(h)) if p == h => "NEW",
+                _ => "",
+            };
+            out += &format!(" {:>6} {:>2}  {:<8} {:<6} {:<10} {} {}\n",
+                            marker,
+                            pos + 1,
+                            result.score,
+                            result.lines,
+                            format_time(result.time_secs),
+                            format_date(result.date),
+                            result.seed);
+        }
+        out
+    }
+}
+
+pub fn format_time(secs: f32) -> String {
+    format!("{}:{:06.3}", (secs / 60.0) as u32, secs % 60.0)
+}
+
+/// Formats a unix timestamp as a (UTC) date
+pub fn format_date(timestamp: u64) -> String {
+    // Days to civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = (timestamp / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(mode: &str, score: u32, time_secs: f32) -> GameResult {
+        GameResult {
+            mode: mode.to_string(),
+            score,
+            lines: 0,
+            time_secs,
+            date: 0,
+            seed: 0,
+            replay_file: None,
+            splits: Vec::new(),
+        }
+    }
+
+    fn empty() -> ScoreTable {
+        ScoreTable { version: SCORES_VERSION, results: Vec::new() }
+    }
+
+    #[test]
+    fn ranks_by_score_then_time() {
+        let mut table = empty();
+        assert_eq!(table.add(result("Free play", 100, 60.0), Ranking::Score), Some(0));
+        assert_eq!(table.add(result("Free play", 200, 90.0), Ranking::Score), Some(0));
+        assert_eq!(table.add(result("Free play", 200, 80.0), Ranking::Score), Some(0));
+        assert_eq!(table.add(result("Free play", 150, 10.0), Ranking::Score), Some(2));
+        assert_eq!(table.best("Free play", Ranking::Score).unwrap().time_secs, 80.0);
+    }
+
+    #[test]
+    fn ranks_by_time() {
+        let mut table = empty();
+        table.add(result("Sprint", 0, 60.0), Ranking::Time);
+        assert_eq!(table.add(result("Sprint", 0, 70.0), Ranking::Time), Some(1));
+        // Ties go to the earlier result
+        assert_eq!(table.add(result("Sprint", 0, 60.0), Ranking::Time), Some(1));
+        assert_eq!(table.add(result("Sprint", 0, 50.0), Ranking::Time), Some(0));
+    }
+
+    #[test]
+    fn keeps_only_the_table_of_each_mode() {
+        let mut table = empty();
+        table.add(result("Sprint", 0, 10.0), Ranking::Time);
+        for i in 0..TABLE_LEN as u32 + 5 {
+            table.add(result("Free play", i, 0.0), Ranking::Score);
+        }
+        assert_eq!(table.add(result("Free play", 0, 0.0), Ranking::Score), None);
+        assert_eq!(table.results.len(), TABLE_LEN + 1);
+        assert_eq!(table.best("Free play", Ranking::Score).unwrap().score, TABLE_LEN as u32 + 4);
+        assert_eq!(table.best("Sprint", Ranking::Time).unwrap().time_secs, 10.0);
+    }
+
+    #[test]
+    fn formats_times_and_dates() {
+        assert_eq!(format_time(83.5), "1:23.500");
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(951_782_400), "2000-02-29");
+    }
+}
@@ -47,4 +47,7 @@ void main() {
     if (pos.y > (v_zoom * -5.0) && pos.y <= (v_zoom * 135.0)) {
         out_color *= 0.1;
     }
+
+    // The background is opaque, however the grid lines scaled the alpha
+    out_color.a = 1.0;
 }
@@ -14,7 +14,7 @@ layout(location = 2) flat out vec2 v_scroll_offset;
 layout(location = 3) flat out float v_zoom;
 
 void main() {
-    gl_Position = vec4(a_position, 0.0000001, 1.0);
+    gl_Position = vec4(a_position, 0.0, 1.0);
     v_position = a_position;
     v_resolution = u_resolution;
     v_scroll_offset = u_scroll_offset;
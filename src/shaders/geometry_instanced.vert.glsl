@@ -0,0 +1,61 @@
+#version 450
+
+layout(std140, set = 0, binding = 0)
+uniform Globals {
+    vec2 u_resolution;
+    vec2 u_scroll_offset;
+    float u_zoom;
+    float u_stroke_width;
+    float u_time;
+};
+
+layout(location = 0) in vec2 a_position;
+layout(location = 1) in vec2 a_normal;
+layout(location = 2) in int a_prim_id;
+
+// The primitive of the instance, read straight from the primitive buffer
+layout(location = 3) in vec4 i_color;
+layout(location = 4) in vec4 i_color_stroke;
+layout(location = 5) in vec2 i_translate;
+layout(location = 6) in float i_width;
+layout(location = 7) in float i_angle;
+layout(location = 8) in float i_scale;
+layout(location = 9) in int i_shading;
+layout(location = 10) in vec2 i_wave;
+
+layout(location = 0) out vec4 v_color;
+
+const int SHADING_GRADIENT = 1;
+
+// Lights up the outline of empty cells as two waves run over the field, one down and one across
+vec4 wave_stroke(vec2 wave) {
+    float w = abs(wave.y - abs(sin(u_time * 0.2))) * abs(wave.x - abs(sin(u_time * 0.5)));
+    return vec4(w, w, w, 1.0);
+}
+
+void main() {
+    vec2 invert_y = vec2(1.0, -1.0);
+
+    mat2 rotation = mat2(
+        cos(i_angle), -sin(i_angle),
+        sin(i_angle), cos(i_angle)
+    );
+
+    vec2 local_pos = (a_position * i_scale + a_normal * i_width * u_stroke_width) * rotation;
+    vec2 world_pos = local_pos - u_scroll_offset + i_translate;
+    vec2 transformed_pos = world_pos * u_zoom / (vec2(0.5, 0.5) * u_resolution) * invert_y;
+
+    // Only stroke vertices are extruded along a normal
+    if (a_normal == vec2(0.0)) {
+        v_color = i_color;
+        if (i_shading == SHADING_GRADIENT) {
+            v_color.rgb *= 1.0 - (a_position.x + a_position.y) / 20.0;
+        }
+    } else if (i_wave.x >= 0.0 && i_color.rgb == vec3(0.0)) {
+        v_color = wave_stroke(i_wave);
+    } else {
+        v_color = i_color_stroke;
+    }
+
+    gl_Position = vec4(transformed_pos, 0.0, 1.0);
+}
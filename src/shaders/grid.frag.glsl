@@ -0,0 +1,39 @@
+#version 450
+
+layout(set = 1, binding = 0) uniform utexture2D u_cells;
+layout(set = 1, binding = 1) uniform sampler u_sampler;
+
+layout(std140, set = 1, binding = 2)
+uniform Palette {
+    vec4 colors[16];
+    vec4 border_color;
+    vec4 shades;
+    float cell_size;
+    float border_width;
+    float outline_width;
+    float _pad;
+};
+
+layout(location = 0) in vec2 v_cell_pos;
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    ivec2 size = textureSize(usampler2D(u_cells, u_sampler), 0);
+    ivec2 cell = min(ivec2(floor(v_cell_pos)), size - 1);
+    // Red is the fill color and its shade, green the color of an outline, if any
+    uvec2 value = texelFetch(usampler2D(u_cells, u_sampler), cell, 0).rg;
+
+    vec2 from_edge = min(fract(v_cell_pos), 1.0 - fract(v_cell_pos)) * cell_size;
+    float edge = min(from_edge.x, from_edge.y);
+
+    vec4 color;
+    if (value.g != 0u && edge < outline_width) {
+        color = colors[value.g & 15u];
+    } else if (edge < border_width) {
+        color = border_color;
+    } else {
+        color = colors[value.r & 15u];
+        color.rgb *= shades[int((value.r >> 4) & 3u)];
+    }
+    out_color = vec4(color.rgb * color.a, color.a);
+}
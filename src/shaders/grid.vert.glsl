@@ -0,0 +1,42 @@
+#version 450
+
+layout(std140, set = 0, binding = 0)
+uniform Globals {
+    vec2 u_resolution;
+    vec2 u_scroll_offset;
+    float u_zoom;
+};
+
+layout(set = 1, binding = 0) uniform utexture2D u_cells;
+layout(set = 1, binding = 1) uniform sampler u_sampler;
+
+layout(std140, set = 1, binding = 2)
+uniform Palette {
+    vec4 colors[16];
+    vec4 border_color;
+    vec4 shades;
+    float cell_size;
+    float border_width;
+    float outline_width;
+    float _pad;
+};
+
+// Position within the grid, in cells
+layout(location = 0) out vec2 v_cell_pos;
+
+void main() {
+    // Two triangles covering the whole grid, no vertex buffer needed
+    vec2 corners[6] = vec2[6](
+        vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(0.0, 1.0),
+        vec2(0.0, 1.0), vec2(1.0, 0.0), vec2(1.0, 1.0)
+    );
+    vec2 size = vec2(textureSize(usampler2D(u_cells, u_sampler), 0));
+    vec2 cell_pos = corners[gl_VertexIndex] * size;
+
+    vec2 invert_y = vec2(1.0, -1.0);
+    vec2 world_pos = cell_pos * cell_size - u_scroll_offset;
+    vec2 transformed_pos = world_pos * u_zoom / (vec2(0.5, 0.5) * u_resolution) * invert_y;
+
+    v_cell_pos = cell_pos;
+    gl_Position = vec4(transformed_pos, 0.0, 1.0);
+}
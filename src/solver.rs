@@ -0,0 +1,326 @@
+use std::collections::{HashSet, VecDeque};
+use crate::game::{check_if_free, Game, Piece, Playfield, PLAYFIELD_COLS, PLAYFIELD_ROWS};
+use crate::rotation::RotationSystem;
+use crate::tetrominos::Tetromino;
+
+/// One step of a perfect clear solution
+#[derive(Clone)]
+pub struct Placement {
+    /// Swap the piece with the hold slot before placing it
+    pub hold: bool,
+    /// The piece where it locks, on the board left by the placements before it
+    pub piece: Piece,
+}
+
+/// A board and the pieces known to be coming, to look for a perfect clear in
+#[derive(Clone)]
+pub struct Problem {
+    pub playfield: Playfield,
+    /// The active piece followed by the queue
+    pub pieces: Vec<Tetromino>,
+    pub hold: Option<Tetromino>,
+    pub can_hold: bool,
+    /// Hold was already used for the active piece
+    pub hold_used: bool,
+    pub rotation: &'static dyn RotationSystem,
+}
+
+// Where the search is at: the next piece to deal, the held piece and whether it may be swapped
+type State = (usize, Option<Tetromino>, bool);
+
+impl Problem {
+    /// The board of `game`, with the active piece, hold and the previews the player can see
+    pub fn from_game(game: &Game) -> Self {
+        Problem {
+            playfield: game.playfield,
+            pieces: game.piece.iter().map(|p| p.index).chain(game.previews().copied()).collect(),
+            hold: game.hold,
+            can_hold: game.rules.hold,
+            hold_used: game.hold_used(),
+            rotation: game.rotation,
+        }
+    }
+
+    /// Looks for a perfect clear using at most `max_pieces` pieces, trying the lowest
+    /// possible height first. Returns the placements, in order, if there is one.
+    /// The search gives up after visiting `max_nodes` boards, so it takes a bounded time even
+    /// when there is no perfect clear to find.
+    pub fn solve(&self, max_pieces: usize, max_nodes: usize) -> Option<Vec<Placement>> {
+        let filled = self.playfield.iter().filter(|&&b| b != 0).count();
+        let stack_height = (0..PLAYFIELD_ROWS as usize)
+            .find(|&r| row(&self.playfield, r).iter().any(|&b| b != 0))
+            .map_or(0, |r| PLAYFIELD_ROWS as usize - r);
+        let available = self.pieces.len() + self.hold.iter().count();
+        let max_pieces = max_pieces.min(available);
+
+        let mut nodes_left = max_nodes;
+        for height in stack_height.max(1)..=PLAYFIELD_ROWS as usize {
+            let cells = height * PLAYFIELD_COLS as usize;
+            if cells < filled || !(cells - filled).is_multiple_of(4) {
+                continue;
+            }
+            if (cells - filled) / 4 > max_pieces {
+                break;
+            }
+            let mut search = Search { problem: self, failed: HashSet::new(), nodes_left };
+            let state = (0, self.hold, !self.hold_used);
+            if let Some(mut solution) = search.solve(&self.playfield, height, state) {
+                solution.reverse();
+                return Some(solution);
+            }
+            nodes_left = search.nodes_left;
+            if nodes_left == 0 {
+                break;
+            }
+        }
+        None
+    }
+}
+
+struct Search<'a> {
+    problem: &'a Problem,
+    /// Boards and states already known not to lead to a perfect clear
+    failed: HashSet<(Vec<usize>, State)>,
+    /// Boards that may still be visited before giving up
+    nodes_left: usize,
+}
+
+impl Search<'_> {
+    // Returns the placements in reverse order
+    fn solve(&mut self, playfield: &Playfield, height: usize, state: State) -> Option<Vec<Placement>> {
+        if height == 0 {
+            return Some(Vec::new());
+        }
+        let top = PLAYFIELD_ROWS as usize - height;
+        let key = (playfield[top * PLAYFIELD_COLS as usize..].to_vec(), state);
+        if self.nodes_left == 0 || self.failed.contains(&key) || !can_fill(playfield, top) {
+            return None;
+        }
+        self.nodes_left -= 1;
+
+        for (hold, index, next) in self.options(state) {
+            for piece in reachable(playfield, index, top as i32, self.problem.rotation) {
+                let mut next_field = *playfield;
+                for [c, r] in piece.cells() {
+                    next_field[(r * PLAYFIELD_COLS as i32 + c) as usize] = index;
+                }
+                let lines = clear_lines(&mut next_field);
+                if let Some(mut solution) = self.solve(&next_field, height - lines, next) {
+                    solution.push(Placement { hold, piece });
+                    return Some(solution);
+                }
+            }
+        }
+        // Running out of nodes says nothing about the board
+        if self.nodes_left > 0 {
+            self.failed.insert(key);
+        }
+        None
+    }
+
+    // The pieces that can be placed next, as (uses hold, piece, state after placing it)
+    fn options(&self, (next, hold, may_hold): State) -> Vec<(bool, Tetromino, State)> {
+        let pieces = &self.problem.pieces;
+        let mut options = Vec::new();
+        match pieces.get(next) {
+            Some(&current) => {
+                options.push((false, current, (next + 1, hold, true)));
+                if self.problem.can_hold && may_hold {
+                    match hold {
+                        Some(held) if held != current => options.push((true, held, (next + 1, Some(current), true))),
+                        Some(_) => {}
+                        None => if let Some(&after) = pieces.get(next + 1) {
+                            options.push((true, after, (next + 2, Some(current), true)));
+                        }
+                    }
+                }
+            }
+            // Once the queue runs out the held piece comes out by itself
+            None => if let Some(held) = hold {
+                options.push((false, held, (next, None, true)));
+            }
+        }
+        options
+    }
+}
+
+/// Every position `index` can lock in by shifting, rotating and soft dropping from its spawn,
+/// keeping to the rows from `top` down
+fn reachable(playfield: &Playfield, index: Tetromino, top: i32, rotation: &dyn RotationSystem) -> Vec<Piece> {
+    let spawn = Piece::new(index, rotation);
+    let mut placements: Vec<Piece> = Vec::new();
+    if !check_if_free(spawn.pos, &spawn.matrix, playfield) {
+        return placements;
+    }
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::new();
+    seen.insert((spawn.pos, spawn.rot));
+    queue.push_back(spawn);
+
+    while let Some(piece) = queue.pop_front() {
+        let mut next = Vec::with_capacity(5);
+        for &[dx, dy] in &[[-1, 0], [1, 0], [0, 1]] {
+            let pos = [piece.pos[0] + dx, piece.pos[1] + dy];
+            if check_if_free(pos, &piece.matrix, playfield) {
+                next.push(Piece { pos, ..piece.clone() });
+            }
+        }
+        for &steps in &[1, 3] {
+            let rot = (piece.rot + steps) % 4;
+            let matrix = rotation.shape(index, rot);
+            if let Some([dx, dy]) = rotation.kicks(&piece, rot, playfield).into_iter()
+                .find(|[dx, dy]| check_if_free([piece.pos[0] + dx, piece.pos[1] + dy], &matrix, playfield))
+            {
+                next.push(Piece { index, pos: [piece.pos[0] + dx, piece.pos[1] + dy], rot, matrix });
+            }
+        }
+        for piece in next {
+            if seen.insert((piece.pos, piece.rot)) {
+                queue.push_back(piece);
+            }
+        }
+
+        let landed = !check_if_free([piece.pos[0], piece.pos[1] + 1], &piece.matrix, playfield);
+        let mut cells = piece.cells();
+        cells.sort();
+        if landed && cells.iter().all(|[_, r]| *r >= top) &&
+            !placements.iter().any(|p| { let mut other = p.cells(); other.sort(); other == cells }) {
+            placements.push(piece);
+        }
+    }
+    placements
+}
+
+// Every enclosed area of empty cells from row `top` down has to be filled by whole pieces
+fn can_fill(playfield: &Playfield, top: usize) -> bool {
+    let cols = PLAYFIELD_COLS as usize;
+    let mut seen = [false; (PLAYFIELD_COLS * PLAYFIELD_ROWS) as usize];
+    for start in top * cols..playfield.len() {
+        if seen[start] || playfield[start] != 0 {
+            continue;
+        }
+        let mut size = 0;
+        let mut stack = vec![start];
+        seen[start] = true;
+        while let Some(i) = stack.pop() {
+            size += 1;
+            let (c, r) = (i % cols, i / cols);
+            let neighbours = [
+                if c > 0 { Some(i - 1) } else { None },
+                if c + 1 < cols { Some(i + 1) } else { None },
+                if r > top { Some(i - cols) } else { None },
+                if i + cols < playfield.len() { Some(i + cols) } else { None },
+            ];
+            for n in neighbours.iter().flatten() {
+                if !seen[*n] && playfield[*n] == 0 {
+                    seen[*n] = true;
+                    stack.push(*n);
+                }
+            }
+        }
+        if size % 4 != 0 {
+            return false;
+        }
+    }
+    true
+}
+
+fn row(playfield: &Playfield, r: usize) -> &[usize] {
+    &playfield[r * PLAYFIELD_COLS as usize..(r + 1) * PLAYFIELD_COLS as usize]
+}
+
+fn clear_lines(playfield: &mut Playfield) -> usize {
+    let cols = PLAYFIELD_COLS as usize;
+    let kept: Vec<usize> = (0..PLAYFIELD_ROWS as usize)
+        .filter(|&r| row(playfield, r).contains(&0))
+        .collect();
+    let cleared = PLAYFIELD_ROWS as usize - kept.len();
+    if cleared > 0 {
+        let old = *playfield;
+        for block in playfield[..cleared * cols].iter_mut() {
+            *block = 0;
+        }
+        for (dst, src) in (cleared..).zip(kept) {
+            playfield[dst * cols..(dst + 1) * cols].copy_from_slice(row(&old, src));
+        }
+    }
+    cleared
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::puzzle;
+    use crate::rotation::Rotation;
+    use crate::tetrominos;
+
+    fn problem(field: &str, pieces: &str) -> Problem {
+        Problem {
+            playfield: puzzle::parse_field(field).unwrap(),
+            pieces: puzzle::parse_sequence(pieces).unwrap(),
+            hold: None,
+            can_hold: true,
+            hold_used: false,
+            rotation: Rotation::Srs.system(),
+        }
+    }
+
+    const TWO_ROWS: &str = "....SSZZTT\n....SSZZTT";
+
+    #[test]
+    fn solves_the_perfect_clear_puzzle() {
+        let puzzles = puzzle::load_puzzles();
+        let puzzle = puzzles.iter().find(|p| p.name == "Perfect clear").unwrap();
+        let problem = Problem {
+            playfield: puzzle.playfield,
+            pieces: puzzle.sequence.clone(),
+            hold: None,
+            can_hold: true,
+            hold_used: false,
+            rotation: puzzle.rotation.system(),
+        };
+        let solution = problem.solve(10, 1000).unwrap();
+        assert_eq!(solution.len(), 2);
+
+        let mut playfield = puzzle.playfield;
+        for placement in &solution {
+            assert!(!placement.hold);
+            for [c, r] in placement.piece.cells() {
+                playfield[(r * PLAYFIELD_COLS as i32 + c) as usize] = placement.piece.index;
+            }
+        }
+        assert_eq!(clear_lines(&mut playfield), 2);
+        assert!(playfield.iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn finds_no_perfect_clear_when_there_is_none() {
+        assert!(problem(TWO_ROWS, "SS").solve(10, 1000).is_none());
+        assert!(problem(TWO_ROWS, "IOT").solve(10, 1000).is_none());
+    }
+
+    #[test]
+    fn holds_a_piece_that_doesnt_fit() {
+        let solution = problem(TWO_ROWS, "SOO").solve(10, 1000).unwrap();
+        assert_eq!(solution.len(), 2);
+        assert!(solution[0].hold);
+        assert!(solution.iter().all(|p| p.piece.index == tetrominos::TO));
+
+        let no_hold = Problem { can_hold: false, ..problem(TWO_ROWS, "SOO") };
+        assert!(no_hold.solve(10, 1000).is_none());
+    }
+
+    #[test]
+    fn gives_up_past_the_limits() {
+        assert!(problem(TWO_ROWS, "OO").solve(1, 1000).is_none());
+        assert!(problem(TWO_ROWS, "OO").solve(10, 1).is_none());
+        assert!(problem(TWO_ROWS, "OO").solve(10, 2).is_some());
+    }
+
+    #[test]
+    fn clears_full_lines() {
+        let mut playfield = puzzle::parse_field("X.........\nXXXXXXXXXX").unwrap();
+        assert_eq!(clear_lines(&mut playfield), 1);
+        assert_eq!(playfield, puzzle::parse_field("X.........").unwrap());
+    }
+}
@@ -0,0 +1,158 @@
+use serde::{Serialize, Deserialize};
+use crate::game::LockResult;
+use crate::tetrominos::{self, Tetromino};
+
+const PIECE_TYPES: usize = tetrominos::TL + 1;
+
+/// Running statistics of a game, shown in the window title while playing (toggled with Tab) and in
+/// the end of game summary
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct Stats {
+    pub pieces: u32,
+    pub keys: u32,
+    pub attack: u32,
+    pub lines: u32,
+    pub finesse_faults: u32,
+    /// Number of locked pieces of each type
+    pub distribution: [u32; PIECE_TYPES],
+    /// Longest run of pieces without seeing each type
+    pub longest_drought: [u32; PIECE_TYPES],
+    current_drought: [u32; PIECE_TYPES],
+}
+
+/// Lines of garbage sent by a clear, following the guideline attack table
+pub fn attack(lock: &LockResult) -> u32 {
+    if lock.perfect_clear {
+        return 10;
+    }
+    match (lock.lines, lock.t_spin) {
+        (1, true) => 2,
+        (2, true) => 4,
+        (3, true) => 6,
+        (2, false) => 1,
+        (3, false) => 2,
+        (4, _) => 4,
+        _ => 0,
+    }
+}
+
+impl Stats {
+    pub fn add_lock(&mut self, index: Tetromino, lock: &LockResult) {
+        self.pieces += 1;
+        self.lines += lock.lines;
+        self.attack += attack(lock);
+        if lock.is_finesse_fault() {
+            self.finesse_faults += 1;
+        }
+        self.distribution[index] += 1;
+        for t in tetrominos::TI..=tetrominos::TL {
+            if t == index {
+                self.current_drought[t] = 0;
+            } else {
+                self.current_drought[t] += 1;
+                self.longest_drought[t] = self.longest_drought[t].max(self.current_drought[t]);
+            }
+        }
+    }
+
+    /// Pieces per second
+    pub fn pps(&self, time_secs: f32) -> f32 {
+        if time_secs > 0.0 { self.pieces as f32 / time_secs } else { 0.0 }
+    }
+
+    /// Attack per minute
+    pub fn apm(&self, time_secs: f32) -> f32 {
+        if time_secs > 0.0 { self.attack as f32 * 60.0 / time_secs } else { 0.0 }
+    }
+
+    /// Keys per piece
+    pub fn kpp(&self) -> f32 {
+        if self.pieces > 0 { self.keys as f32 / self.pieces as f32 } else { 0.0 }
+    }
+
+    /// Short single line version for the window title, which is where the HUD lives: the renderer
+    /// has no text yet
+    pub fn format_hud(&self, time_secs: f32) -> String {
+        let distribution: Vec<String> = (tetrominos::TI..=tetrominos::TL)
+            .map(|t| format!("{}{}", tetrominos::NAMES[t], self.distribution[t]))
+            .collect();
+        format!("PPS {:.2} APM {:.1} KPP {:.2} Lines {} Faults {} | {}",
+                self.pps(time_secs), self.apm(time_secs), self.kpp(), self.lines, self.finesse_faults,
+                distribution.join(" "))
+    }
+
+    pub fn format_summary(&self, time_secs: f32) -> String {
+        let mut out = format!(" Pieces {}  Lines {}  Attack {}  Keys {}\n",
+                              self.pieces, self.lines, self.attack, self.keys);
+        out += &format!(" PPS {:.2}  APM {:.1}  KPP {:.2}  Finesse faults {}\n",
+                        self.pps(time_secs), self.apm(time_secs), self.kpp(), self.finesse_faults);
+        out += "  Piece  Count  Longest drought\n";
+        for t in tetrominos::TI..=tetrominos::TL {
+            out += &format!("  {:<5}  {:<5}  {}\n",
+                            tetrominos::NAMES[t], self.distribution[t], self.longest_drought[t]);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clear(lines: u32) -> LockResult {
+        LockResult { lines, ..LockResult::default() }
+    }
+
+    #[test]
+    fn attack_follows_the_guideline_table() {
+        assert_eq!(attack(&clear(0)), 0);
+        assert_eq!(attack(&clear(1)), 0);
+        assert_eq!(attack(&clear(2)), 1);
+        assert_eq!(attack(&clear(3)), 2);
+        assert_eq!(attack(&clear(4)), 4);
+        assert_eq!(attack(&LockResult { t_spin: true, ..clear(2) }), 4);
+        assert_eq!(attack(&LockResult { perfect_clear: true, ..clear(1) }), 10);
+    }
+
+    #[test]
+    fn rates_over_time_and_pieces() {
+        let mut stats = Stats::default();
+        assert_eq!(stats.pps(0.0), 0.0);
+        assert_eq!(stats.apm(0.0), 0.0);
+        assert_eq!(stats.kpp(), 0.0);
+
+        for _ in 0..4 {
+            stats.add_lock(tetrominos::TI, &clear(4));
+        }
+        stats.keys = 10;
+        assert_eq!(stats.pieces, 4);
+        assert_eq!(stats.lines, 16);
+        assert_eq!(stats.attack, 16);
+        assert_eq!(stats.pps(2.0), 2.0);
+        assert_eq!(stats.apm(30.0), 32.0);
+        assert_eq!(stats.kpp(), 2.5);
+    }
+
+    #[test]
+    fn tracks_the_longest_drought_of_each_piece() {
+        let mut stats = Stats::default();
+        for &t in &[tetrominos::TI, tetrominos::TO, tetrominos::TO, tetrominos::TO, tetrominos::TI, tetrominos::TO] {
+            stats.add_lock(t, &clear(0));
+        }
+        assert_eq!(stats.distribution[tetrominos::TI], 2);
+        assert_eq!(stats.distribution[tetrominos::TO], 4);
+        assert_eq!(stats.longest_drought[tetrominos::TI], 3);
+        assert_eq!(stats.longest_drought[tetrominos::TO], 1);
+        assert_eq!(stats.longest_drought[tetrominos::TT], 6);
+    }
+
+    #[test]
+    fn hud_fits_on_one_line() {
+        let mut stats = Stats::default();
+        stats.add_lock(tetrominos::TT, &clear(1));
+        let hud = stats.format_hud(1.0);
+        assert!(!hud.contains('\n'));
+        assert!(hud.starts_with("PPS 1.00 APM 0.0"));
+        assert!(hud.contains("T1"));
+    }
+}
@@ -9,6 +9,13 @@ pub enum TetroShape {
     Odd(TetroShape3)
 }
 
+/// A shape without any solid blocks
+impl Default for TetroShape {
+    fn default() -> Self {
+        TetroShape::Odd([[false; 3]; 3])
+    }
+}
+
 impl TetroShape {
     pub fn is_solid(&self, x: usize, y: usize) -> bool {
         match self {
@@ -20,8 +27,8 @@ impl TetroShape {
 
     pub fn rotated(&self, steps: u8) -> TetroShape {
         match self {
-            TetroShape::Odd(t) => TetroShape::Odd(rotated3(t.clone(), steps)),
-            TetroShape::Even(t) => TetroShape::Even(rotated4(t.clone(), steps)),
+            TetroShape::Odd(t) => TetroShape::Odd(rotated3(*t, steps)),
+            TetroShape::Even(t) => TetroShape::Even(rotated4(*t, steps)),
         }
     }
 }
@@ -58,9 +65,17 @@ fn rotated4(x: TetroShape4, steps: u8) -> TetroShape4 {
 
 pub type Tetromino = usize;
 
-const TI: Tetromino = 1;
+pub const TI: Tetromino = 1;
+pub const TO: Tetromino = 2;
+pub const TT: Tetromino = 3;
+pub const TS: Tetromino = 4;
+pub const TZ: Tetromino = 5;
+pub const TJ: Tetromino = 6;
 pub const TL: Tetromino = 7;
 
+/// Playfield block that isn't part of any tetromino, like the rows of a dig race
+pub const GARBAGE: usize = 8;
+
 pub const RANGE: Range<Tetromino> = TI..TL;
 
 pub const NAMES: [&str; 8] = [
@@ -74,6 +89,11 @@ pub const NAMES: [&str; 8] = [
     "L",
 ];
 
+/// Looks up a tetromino by its single letter name, e.g. 'T'
+pub fn from_name(name: char) -> Option<Tetromino> {
+    (TI..=TL).find(|&t| NAMES[t].starts_with(name))
+}
+
 pub const ALL: [TetroShape; 8] = [
     TetroShape::Odd(TETRO_NONE),
     TetroShape::Even(TETRO_I),
@@ -85,18 +105,14 @@ pub const ALL: [TetroShape; 8] = [
     TetroShape::Odd(TETRO_L),
 ];
 
-type Color = [ f32; 4 ];
-
- fn col_f32( c: u8 ) -> f32 {
-    255.0 / (c as f32)
-}
+pub type Color = [ f32; 4 ];
 
 fn rgba( rgba: u32 ) -> Color {
     [
         (((rgba >> 24) as u8) as f32) / 255.0,
         (((rgba >> 16) as u8) as f32) / 255.0,
         (((rgba >>  8) as u8) as f32) / 255.0,
-        (((rgba >>  0) as u8) as f32) / 255.0,
+        ((rgba as u8) as f32) / 255.0,
     ]
 }
 
@@ -107,16 +123,22 @@ fn rgba( rgba: u32 ) -> Color {
 lazy_static! {
     pub static ref Colors: Vec<Color> = vec!(
         [  0.0, 0.0, 0.0, 0.0 ],
-        rgba(0x00C0C0ff),
-        rgba(0xFDE01Aff),
+        rgba(0x00C0C0FF),
+        rgba(0xFDE01AFF),
         rgba(0x732982ff),
         rgba(0x007940ff),
         rgb(0xD12229),
-        rgba(0x24408Eff),
+        rgba(0x24408EFF),
         rgba(0xf68a1eff),
+        rgb(0x808080),
     );
 }
 
+/// Color of a playfield block, anything unknown is drawn like garbage
+pub fn block_color(block: usize) -> Color {
+    Colors.get(block).copied().unwrap_or(Colors[GARBAGE])
+}
+
 const XX: bool = true;
 const __: bool = false;
 